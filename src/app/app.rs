@@ -1,45 +1,40 @@
 use crate::{
     PiprConfig,
     CmdOutput,
+    RunStatus,
+    RunSummary,
     CommandExecutionRequest,
     CommandExecutionHandler
 };
 use crate::app::main_window::AutocompleteState;
 use crate::app::key_select_menu::KeySelectMenu;
+use crate::app::bookmark_editor::BookmarkEditorState;
 use crate::app::command_list_window::CommandListState;
-use crate::lineeditor::EditorState;
-use crate::commandlist::CommandList;
+use crate::app::history_search::HistorySearchState;
+use crate::app::stage_inspector::StageInspectorState;
+use crate::app::variable_form::VariableFormState;
+use crate::keybindings::Action;
+use crate::lineeditor::{EditorEvent, EditorState};
+use crate::pipr_config::EditorMode;
+use crate::vi_mode::ViState;
+use crate::commandlist::{CommandEntry, CommandList};
+use crate::piped_input::PipedInputReader;
+use crate::shell_lexer;
 use crate::util::VecStringExt;
 
 use crossterm::event::{KeyCode, KeyModifiers};
-
-pub const HELP_TEXT: &str = "\
-F1         Show/hide help
-F2         Toggle autoeval
-F3         Toggle Paranoid history (fills up history in autoeval)
-F4         Show/hide history
-Ctrl+B     Show/hide bookmarks
-F5         Open helpviewer
-F6         Open outputviewer
-F7         When the cursor is on a `|` symbol, cache the output of everything before that |
-Ctrl+S     Save bookmark
-Alt+Return Newline
-Ctrl+U     Clear Command
-Ctrl+P     Previous in history
-Ctrl+N     Next in history
-Ctrl+V     Insert snippet (press corresponding key to choose)
-
-disable a line by starting it with a #
-this will simply exclude the line from the executed command.
-
-Config file is in
-~/.config/pipr/pipr.toml";
+use itertools::Itertools;
+use std::time::Instant;
 
 pub enum WindowState {
     Main,
     TextView(String, String),
     BookmarkList(CommandListState),
+    BookmarkEditor(Box<BookmarkEditorState>),
+    /// filling in the variables of a bookmark before loading it
+    VariableForm(Box<VariableFormState>),
     HistoryList(CommandListState),
+    StageInspector(StageInspectorState),
 }
 
 pub enum KeySelectMenuType {
@@ -70,13 +65,34 @@ impl CachedCommandPart {
 
 pub struct App {
     pub input_state: EditorState,
+    /// the state of vi-style editing of the command, if the editor_mode is vi
+    pub vi_state: Option<ViState>,
+    /// text that was copied in the editor and still needs to be sent to the terminal's clipboard
+    pub pending_clipboard: Option<String>,
+    /// sample data that is piped into the command while the sample input pane is open
+    pub sample_input_state: EditorState,
+    pub sample_input_open: bool,
+    /// the data that was piped into pipr's stdin, if any
+    pub piped_input: Option<Vec<String>>,
+    /// reads the piped data while pipr is running
+    pub piped_input_reader: Option<PipedInputReader>,
+    /// whether key events go to the sample input instead of the command
+    pub sample_input_focused: bool,
     pub command_output: String,
     pub command_error: String,
     pub autoeval_mode: bool,
     pub last_executed_cmd: String,
+    /// the command that was last sent to be evaluated
+    pub last_executed_entry: Option<CommandEntry>,
+    /// summary of the last finished evaluation
+    pub last_run: Option<RunSummary>,
     pub paranoid_history_mode: bool,
     pub window_state: WindowState,
     pub bookmarks: CommandList,
+    /// the read-only bookmarks of the shared collections
+    pub bookmark_collections: Vec<CommandEntry>,
+    /// why some of the bookmark collections could not be loaded, shown in the ui
+    pub collection_error: Option<String>,
     pub history: CommandList,
     pub history_idx: Option<usize>,
     pub execution_handler: CommandExecutionHandler,
@@ -85,6 +101,8 @@ pub struct App {
     pub opened_key_select_menu: Option<KeySelectMenu<KeySelectMenuType>>,
     pub raw_mode: bool,
     pub autocomplete_state: Option<AutocompleteState>,
+    /// the running Ctrl+R search through the history, if any
+    pub history_search: Option<HistorySearchState>,
 
     /// Part of a command can be cached, so it will not be reevluated on every execution.
     pub cached_command_part: Option<CachedCommandPart>,
@@ -92,6 +110,14 @@ pub struct App {
     /// number from 0-4 showing an animation that shows some process being executed
     pub is_processing_state: Option<u8>,
 
+    /// when set, the content will be evaluated at this point in time.
+    /// Used to only evaluate once the user stopped typing in autoeval mode.
+    pub autoeval_deadline: Option<Instant>,
+
+    /// true once the currently running command has sent partial output,
+    /// which then replaces the output of the previous run.
+    pub receiving_partial_output: bool,
+
     /// A (stdin, command) that should be executed in the main screen.
     /// this will be taken ( and thus reset ) and handled by the ui module.
     pub should_jump_to_other_cmd: Option<(Option<String>, std::process::Command)>,
@@ -107,15 +133,31 @@ impl App {
     ) -> App {
         App {
             autocomplete_state: None,
+            history_search: None,
             window_state: WindowState::Main,
             input_state: EditorState::new(),
+            vi_state: if config.editor_mode == EditorMode::Vi {
+                Some(ViState::new())
+            } else {
+                None
+            },
+            pending_clipboard: None,
+            sample_input_state: EditorState::new(),
+            sample_input_open: false,
+            piped_input: None,
+            piped_input_reader: None,
+            sample_input_focused: false,
             command_output: "".into(),
             command_error: "".into(),
             last_executed_cmd: "".into(),
+            last_executed_entry: None,
+            last_run: None,
             autoeval_mode: config.autoeval_mode_default,
             paranoid_history_mode: config.paranoid_history_mode_default,
             should_quit: false,
             is_processing_state: None,
+            receiving_partial_output: false,
+            autoeval_deadline: None,
             history_idx: None,
             cached_command_part: None,
             opened_key_select_menu: None,
@@ -124,19 +166,61 @@ impl App {
             raw_mode,
             config,
             bookmarks,
+            bookmark_collections: Vec::new(),
+            collection_error: None,
             history,
         }
     }
 
     pub fn on_cmd_output(&mut self, process_result: CmdOutput) {
+        if let CmdOutput::Partial(chunk) = process_result {
+            if self.receiving_partial_output {
+                self.command_output.push_str(&chunk);
+            } else {
+                self.receiving_partial_output = true;
+                self.command_output = chunk;
+            }
+            return;
+        }
+
         self.is_processing_state = None;
+        // the output a failed run streamed so far must not look like the result of a successful run
+        let failed = match &process_result {
+            CmdOutput::Finished(result) => {
+                !result.summary.status.success() && result.summary.status != RunStatus::OutputTruncated
+            }
+            _ => true,
+        };
+        if failed && self.receiving_partial_output {
+            self.command_output.clear();
+        }
+        self.receiving_partial_output = false;
         match process_result {
-            CmdOutput::Ok(stdout) => {
-                if self.paranoid_history_mode {
-                    self.history.push(self.input_state.content_to_commandentry());
+            CmdOutput::Partial(_) => {}
+            CmdOutput::Finished(result) => {
+                if result.summary.status.success() {
+                    if self.paranoid_history_mode {
+                        self.history.push(self.input_state.content_to_commandentry());
+                    }
+                    self.command_output = result.stdout;
+                    self.command_error = String::new();
+                } else if result.summary.status == RunStatus::OutputTruncated {
+                    self.command_output = result.stdout;
+                    self.command_error = result.stderr;
+                } else if result.stderr.is_empty() {
+                    self.command_error = match result.summary.status {
+                        RunStatus::TimedOut => "Command timed out".to_string(),
+                        RunStatus::Cancelled => "Command cancelled".to_string(),
+                        status => format!("Command failed ({})", status),
+                    };
+                } else {
+                    self.command_error = result.stderr;
                 }
-                self.command_output = stdout;
-                self.command_error = String::new();
+
+                if let Some(executed_entry) = &self.last_executed_entry {
+                    self.history.record_run(executed_entry, result.summary);
+                }
+                self.last_run = Some(result.summary);
             }
             CmdOutput::NotOk(stderr) => self.command_error = stderr,
         }
@@ -148,28 +232,62 @@ impl App {
     }
 
     pub async fn execute_content(&mut self) {
+        self.autoeval_deadline = None;
         let lines = self.input_state.content_lines().clone();
         let lines = match self.cached_command_part {
             Some(CachedCommandPart { end_line, end_col, .. }) => lines.split_strings_at_offset(end_line, end_col).1,
             _ => lines,
         };
 
-        let command = lines
-            .iter()
-            .filter(|line| !line.starts_with('#'))
-            .cloned()
-            .collect::<Vec<String>>();
+        let command = shell_lexer::strip_comments(&lines.join("\n"));
         let command = if self.raw_mode {
-            command.join("\n")
+            command
         } else {
-            command.join(" ")
+            command.lines().join(" ")
         };
 
-        let execution_request =
-            CommandExecutionRequest::new(command, self.cached_command_part.as_ref().map(|x| x.cached_output.to_owned()));
+        let stdin = match &self.cached_command_part {
+            Some(cached_command_part) => Some(cached_command_part.cached_output.to_owned()),
+            None => self.input_data().map(|lines| lines.to_vec()),
+        };
+        let execution_request = CommandExecutionRequest::new(command, stdin);
         self.execution_handler.execute(execution_request).await;
         self.is_processing_state = Some(0);
+        self.receiving_partial_output = false;
         self.last_executed_cmd = self.input_state.content_str();
+        self.last_executed_entry = Some(self.input_state.content_to_commandentry());
+    }
+
+    /// the data that is piped into the command: the sample input if it is open,
+    /// otherwise the data that was piped into pipr
+    pub fn input_data(&self) -> Option<&[String]> {
+        let lines = self.sample_input_state.content_lines();
+        if !self.sample_input_open {
+            self.piped_input.as_deref()
+        } else if lines.len() == 1 && lines[0].is_empty() {
+            // an empty sample input closes stdin right away, instead of piping a single empty line
+            Some(&[])
+        } else {
+            Some(lines)
+        }
+    }
+
+    /// evaluate the content once the autoeval debounce interval has passed without further changes
+    pub async fn schedule_autoeval(&mut self) {
+        if self.config.autoeval_debounce.as_millis() == 0 {
+            self.execute_content().await;
+        } else {
+            self.autoeval_deadline = Some(Instant::now() + self.config.autoeval_debounce);
+        }
+    }
+
+    pub async fn on_autoeval_deadline(&mut self) {
+        self.autoeval_deadline = None;
+        self.execute_content().await;
+    }
+
+    pub async fn cancel_execution(&mut self) {
+        self.execution_handler.cancel().await;
     }
 
     fn toggle_history_list(&mut self) {
@@ -185,10 +303,18 @@ impl App {
 
     fn toggle_bookmark_list(&mut self) {
         match self.window_state {
-            WindowState::BookmarkList(_) => self.window_state = WindowState::Main,
+            WindowState::BookmarkList(_) | WindowState::BookmarkEditor(_) | WindowState::VariableForm(_) => {
+                self.window_state = WindowState::Main
+            }
             _ => {
                 self.history.push(self.input_state.content_to_commandentry());
-                let entries = self.bookmarks.entries().clone();
+                // the collections go above the personal bookmarks, so the latest bookmark is still selected first
+                let entries = self
+                    .bookmark_collections
+                    .iter()
+                    .chain(self.bookmarks.entries())
+                    .cloned()
+                    .collect();
                 self.window_state = WindowState::BookmarkList(CommandListState::new(entries, None));
             }
         }
@@ -197,20 +323,65 @@ impl App {
     fn toggle_help_window(&mut self) {
         match self.window_state {
             WindowState::TextView(_, _) => self.window_state = WindowState::Main,
-            _ => self.window_state = WindowState::TextView("Help".to_string(), HELP_TEXT.to_string()),
+            _ => self.window_state = WindowState::TextView("Help".to_string(), self.config.keybindings.help_text()),
+        }
+    }
+
+    fn toggle_stage_inspector(&mut self) {
+        match self.window_state {
+            WindowState::StageInspector(_) => self.window_state = WindowState::Main,
+            _ => {
+                let inspector_state = StageInspectorState::inspect(
+                    self.input_state.content_lines(),
+                    &self.execution_handler.execution_mode,
+                    &self.execution_handler.eval_environment,
+                    &self.execution_handler.resource_limits,
+                    self.input_data(),
+                    self.raw_mode,
+                );
+                self.window_state = WindowState::StageInspector(inspector_state);
+            }
         }
     }
 
     pub async fn on_tui_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
-        let control_pressed = modifiers.contains(KeyModifiers::CONTROL);
-        match code {
-            KeyCode::F(1) => self.toggle_help_window(),
-            KeyCode::Char('b') if control_pressed => self.toggle_bookmark_list(),
-            KeyCode::F(4) => self.toggle_history_list(),
+        match self.config.keybindings.action(code, modifiers) {
+            Some(Action::ToggleHelp) => self.toggle_help_window(),
+            Some(Action::ToggleBookmarks) => self.toggle_bookmark_list(),
+            Some(Action::ToggleHistory) => self.toggle_history_list(),
+            Some(Action::ToggleStageInspector) => self.toggle_stage_inspector(),
             _ => self.handle_window_specific_event(code, modifiers).await,
         }
     }
 
+    /// insert pasted text into the focused text field.
+    /// Without a text field to paste into, the text is handled like typed keys.
+    pub async fn on_paste(&mut self, text: String) {
+        match self.window_state {
+            WindowState::Main => {
+                if !self.paste_into_main_window(&text).await {
+                    self.type_pasted_keys(&text).await;
+                }
+            }
+            WindowState::BookmarkEditor(ref mut state) => state.apply_editor_event(EditorEvent::InsertText(text)),
+            WindowState::VariableForm(ref mut state) => state.apply_editor_event(EditorEvent::InsertText(text)),
+            WindowState::BookmarkList(ref mut state) | WindowState::HistoryList(ref mut state) if state.filter.is_some() => {
+                // the filter is a single line
+                for c in text.chars() {
+                    state.apply_event(KeyCode::Char(if c == '\n' || c == '\r' { ' ' } else { c }));
+                }
+            }
+            _ => self.type_pasted_keys(&text).await,
+        }
+    }
+
+    async fn type_pasted_keys(&mut self, text: &str) {
+        for c in text.chars() {
+            let code = if c == '\n' || c == '\r' { KeyCode::Enter } else { KeyCode::Char(c) };
+            self.on_tui_event(code, KeyModifiers::NONE).await;
+        }
+    }
+
     pub async fn handle_window_specific_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
         let window_state = &mut self.window_state;
         match window_state {
@@ -218,22 +389,75 @@ impl App {
             WindowState::TextView(_, _) => self.window_state = WindowState::Main,
 
             WindowState::BookmarkList(state) => match code {
-                KeyCode::Esc => {
-                    self.bookmarks.set_entries(state.list.clone());
+                // Esc first closes the filter
+                KeyCode::Esc if state.filter.is_none() => {
+                    self.bookmarks.set_entries(state.personal_entries());
                     self.window_state = WindowState::Main;
                 }
                 KeyCode::Enter => {
-                    if let Some(entry) = state.selected_entry() {
-                        self.input_state.load_commandentry(entry);
-                        self.cached_command_part = None;
+                    self.bookmarks.set_entries(state.personal_entries());
+                    if let WindowState::BookmarkList(state) = std::mem::replace(&mut self.window_state, WindowState::Main) {
+                        // bookmarks with variables are only loaded once they are filled in
+                        match VariableFormState::open(state) {
+                            Ok(form) => self.window_state = WindowState::VariableForm(Box::new(form)),
+                            Err(state) => {
+                                if let Some(entry) = state.selected_entry() {
+                                    self.input_state.load_commandentry(entry);
+                                    self.cached_command_part = None;
+                                }
+                            }
+                        }
+                    }
+                }
+                KeyCode::Char('c') if state.filter.is_none() => state.copy_selected_to_personal(),
+                // bookmarks of shared collections can't be edited
+                KeyCode::Char('e')
+                    if state.filter.is_none() && state.selected_entry().is_some_and(|entry| entry.bookmark.collection.is_none()) =>
+                {
+                    if let WindowState::BookmarkList(state) = std::mem::replace(&mut self.window_state, WindowState::Main) {
+                        self.window_state = WindowState::BookmarkEditor(Box::new(BookmarkEditorState::new(state)));
                     }
-                    self.bookmarks.set_entries(state.list.clone());
-                    self.window_state = WindowState::Main;
                 }
                 _ => state.apply_event(code),
             },
-            WindowState::HistoryList(state) => match code {
+            WindowState::BookmarkEditor(state) => match code {
+                KeyCode::Esc | KeyCode::Enter => {
+                    if let WindowState::BookmarkEditor(state) = std::mem::replace(&mut self.window_state, WindowState::Main) {
+                        let list_state = if code == KeyCode::Enter { state.save() } else { state.cancel() };
+                        self.bookmarks.set_entries(list_state.personal_entries());
+                        self.window_state = WindowState::BookmarkList(list_state);
+                    }
+                }
+                KeyCode::Tab | KeyCode::Down => state.focus_next(),
+                KeyCode::BackTab | KeyCode::Up => state.focus_previous(),
+                _ => {
+                    if let Some(event) = self.config.keybindings.editor_event(code, modifiers) {
+                        state.apply_editor_event(event);
+                    }
+                }
+            },
+            WindowState::VariableForm(state) => match code {
                 KeyCode::Esc => {
+                    if let WindowState::VariableForm(state) = std::mem::replace(&mut self.window_state, WindowState::Main) {
+                        self.window_state = WindowState::BookmarkList(state.cancel());
+                    }
+                }
+                KeyCode::Enter => {
+                    self.input_state.load_commandentry(&CommandEntry::new(state.resolved_lines()));
+                    self.cached_command_part = None;
+                    self.window_state = WindowState::Main;
+                }
+                KeyCode::Tab | KeyCode::Down => state.focus_next(),
+                KeyCode::BackTab | KeyCode::Up => state.focus_previous(),
+                _ => {
+                    if let Some(event) = self.config.keybindings.editor_event(code, modifiers) {
+                        state.apply_editor_event(event);
+                    }
+                }
+            },
+            WindowState::HistoryList(state) => match code {
+                // Esc first closes the filter
+                KeyCode::Esc if state.filter.is_none() => {
                     self.history.set_entries(state.list.clone());
                     self.window_state = WindowState::Main;
                 }
@@ -248,10 +472,24 @@ impl App {
                 }
                 _ => state.apply_event(code),
             },
+            WindowState::StageInspector(state) => match (code, self.config.keybindings.action(code, modifiers)) {
+                (KeyCode::Esc, _) => self.window_state = WindowState::Main,
+                (_, Some(Action::CancelExecution)) => state.cancel(),
+                _ => state.apply_event(code),
+            },
         }
     }
 
     pub fn on_tick(&mut self) {
-        self.is_processing_state = self.is_processing_state.map(|x| (x + 1) % 6)
+        self.is_processing_state = self.is_processing_state.map(|x| (x + 1) % 6);
+        if let WindowState::StageInspector(state) = &mut self.window_state {
+            state.receive_results();
+        }
+        if let (Some(reader), Some(piped_input)) = (self.piped_input_reader.as_mut(), self.piped_input.as_mut()) {
+            // evaluate the command on the new data, unless it's running on the sample input
+            if reader.receive_lines(piped_input) && self.autoeval_mode && !self.sample_input_open {
+                self.autoeval_deadline = Some(Instant::now() + self.config.autoeval_debounce);
+            }
+        }
     }
 }
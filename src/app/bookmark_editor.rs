@@ -0,0 +1,108 @@
+use crate::app::command_list_window::CommandListState;
+use crate::bookmarks::BookmarkInfo;
+use crate::lineeditor::{EditorEvent, EditorState};
+
+/// the labels of the fields of the form, in order
+pub const FIELD_NAMES: [&str; 3] = ["Name", "Description", "Tags"];
+
+/// a form to edit the name, description and tags of the selected bookmark
+pub struct BookmarkEditorState {
+    /// the bookmark list the form was opened from, which is shown again once it's closed
+    list_state: CommandListState,
+    pub fields: [EditorState; 3],
+    pub focused_field: usize,
+}
+
+impl BookmarkEditorState {
+    pub fn new(list_state: CommandListState) -> BookmarkEditorState {
+        let info = list_state.selected_entry().map(|entry| entry.bookmark.clone()).unwrap_or_default();
+        let mut fields = [EditorState::new(), EditorState::new(), EditorState::new()];
+        for (field, content) in fields.iter_mut().zip(&[info.name, info.description, info.tags.join(", ")]) {
+            field.set_content(vec![content.clone()]);
+        }
+        BookmarkEditorState {
+            list_state,
+            fields,
+            focused_field: 0,
+        }
+    }
+
+    pub fn list_state(&self) -> &CommandListState {
+        &self.list_state
+    }
+
+    pub fn focus_next(&mut self) {
+        self.focused_field = (self.focused_field + 1) % self.fields.len();
+    }
+
+    pub fn focus_previous(&mut self) {
+        self.focused_field = (self.focused_field + self.fields.len() - 1) % self.fields.len();
+    }
+
+    /// edit the focused field. The fields are single lines, so newlines are not inserted.
+    pub fn apply_editor_event(&mut self, event: EditorEvent) {
+        let event = match event {
+            EditorEvent::NewLine => return,
+            EditorEvent::InsertText(text) => EditorEvent::InsertText(text.replace('\n', " ")),
+            event => event,
+        };
+        self.fields[self.focused_field].apply_event(event);
+    }
+
+    pub fn bookmark_info(&self) -> BookmarkInfo {
+        let [name, description, tags] = &self.fields;
+        BookmarkInfo {
+            name: name.content_str().trim().to_owned(),
+            description: description.content_str().trim().to_owned(),
+            tags: BookmarkInfo::parse_tags(&tags.content_str()),
+            collection: None,
+        }
+    }
+
+    /// store the edited info in the selected bookmark, and return to the list
+    pub fn save(self) -> CommandListState {
+        let info = self.bookmark_info();
+        let mut list_state = self.list_state;
+        if let Some(entry) = list_state.selected_idx.and_then(|idx| list_state.list.get_mut(idx)) {
+            entry.bookmark = info;
+        }
+        list_state.update_filter(false);
+        list_state
+    }
+
+    /// return to the list without changing the bookmark
+    pub fn cancel(self) -> CommandListState {
+        self.list_state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::commandlist::CommandEntry;
+
+    fn type_text(state: &mut BookmarkEditorState, text: &str) {
+        for c in text.chars() {
+            state.apply_editor_event(EditorEvent::NewCharacter(c));
+        }
+    }
+
+    #[test]
+    fn test_edit_bookmark() {
+        let entries = vec![CommandEntry::new(vec!["ls".to_string()]), CommandEntry::new(vec!["ps".to_string()])];
+        let mut state = BookmarkEditorState::new(CommandListState::new(entries, Some(0)));
+        type_text(&mut state, " list ");
+        state.apply_editor_event(EditorEvent::NewLine);
+        state.focus_previous();
+        type_text(&mut state, "#files, dir");
+        state.focus_next();
+        type_text(&mut state, "x");
+
+        let list_state = state.save();
+        let info = &list_state.list[0].bookmark;
+        assert_eq!(info.name, "list x");
+        assert_eq!(info.tags, vec!["files", "dir"]);
+        assert_eq!(info.description, "");
+        assert_eq!(list_state.list[1].bookmark, BookmarkInfo::default());
+    }
+}
@@ -1,56 +1,144 @@
 use crate::commandlist::CommandEntry;
+use crate::fuzzy::fuzzy_match;
 use crossterm::event::KeyCode;
 
 pub struct CommandListState {
     pub list: Vec<CommandEntry>,
     pub selected_idx: Option<usize>,
     recently_deleted: Vec<CommandEntry>,
+    /// the fuzzy filter query, typed after pressing `/`
+    pub filter: Option<String>,
+    /// indices into `list` of the entries that are shown, with the char indices matched by the filter
+    visible: Vec<(usize, Vec<usize>)>,
+}
+
+/// how an entry is shown in the list, on a single line. Bookmarks start with their name and tags,
+/// so the filter matches those as well.
+pub fn display_string(entry: &CommandEntry) -> String {
+    let command = entry.as_string().replace("\n", " ↵ ");
+    match entry.bookmark.label() {
+        label if label.is_empty() => command,
+        label => format!("{} │ {}", label, command),
+    }
 }
 
 impl CommandListState {
     pub fn new(list: Vec<CommandEntry>, selected_idx: Option<usize>) -> CommandListState {
-        CommandListState {
+        let mut state = CommandListState {
             selected_idx: selected_idx.or(if list.is_empty() { None } else { Some(list.len() - 1) }),
             list,
             recently_deleted: Vec::new(),
-        }
+            filter: None,
+            visible: Vec::new(),
+        };
+        state.update_filter(false);
+        state
     }
     pub fn selected_entry(&self) -> Option<&CommandEntry> {
         self.selected_idx.and_then(|idx| self.list.get(idx))
     }
 
+    /// the entries that are shown, in order, with their index in `list` and the char indices matched by the filter
+    pub fn visible_entries(&self) -> impl Iterator<Item = (usize, &CommandEntry, &[usize])> {
+        self.visible
+            .iter()
+            .map(move |(idx, positions)| (*idx, &self.list[*idx], positions.as_slice()))
+    }
+
+    /// the position of the selected entry among the visible ones
+    pub fn selected_position(&self) -> Option<usize> {
+        let selected_idx = self.selected_idx?;
+        self.visible.iter().position(|(idx, _)| *idx == selected_idx)
+    }
+
+    /// recompute the visible entries. Matches are sorted by their score, with the best one at the bottom.
+    /// If the selected entry is no longer visible, or `select_best` is set, the bottom entry gets selected.
+    pub fn update_filter(&mut self, select_best: bool) {
+        self.visible = match &self.filter {
+            None => (0..self.list.len()).map(|idx| (idx, Vec::new())).collect(),
+            Some(query) => {
+                let mut matches = self
+                    .list
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, entry)| fuzzy_match(query, &display_string(entry)).map(|m| (m.score, idx, m.positions)))
+                    .collect::<Vec<_>>();
+                matches.sort_by_key(|(score, idx, _)| (*score, *idx));
+                matches.into_iter().map(|(_, idx, positions)| (idx, positions)).collect()
+            }
+        };
+        if select_best || self.selected_position().is_none() {
+            self.selected_idx = self.visible.last().map(|(idx, _)| *idx);
+        }
+    }
+
+    /// the entries that don't come from a shared bookmark collection
+    pub fn personal_entries(&self) -> Vec<CommandEntry> {
+        self.list.iter().filter(|entry| entry.bookmark.collection.is_none()).cloned().collect()
+    }
+
+    /// add a copy of the selected bookmark of a shared collection to the personal bookmarks, and select it.
+    /// If it's already a personal bookmark, that one is selected instead.
+    pub fn copy_selected_to_personal(&mut self) {
+        let entry = match self.selected_entry() {
+            Some(entry) if entry.bookmark.collection.is_some() => entry.clone(),
+            _ => return,
+        };
+        let existing = self.list.iter().position(|e| *e == entry && e.bookmark.collection.is_none());
+        self.selected_idx = Some(existing.unwrap_or_else(|| {
+            let mut copy = entry;
+            copy.bookmark.collection = None;
+            self.list.push(copy);
+            self.list.len() - 1
+        }));
+        self.update_filter(false);
+    }
+
+    fn select_position(&mut self, position: usize) {
+        self.selected_idx = self.visible.get(position).or_else(|| self.visible.last()).map(|(idx, _)| *idx);
+    }
+
     pub fn apply_event(&mut self, code: KeyCode) {
-        if let Some(selected_idx) = self.selected_idx {
+        // while filtering, typing edits the query
+        if let Some(query) = self.filter.as_mut() {
             match code {
-                KeyCode::PageUp | KeyCode::Char('g') => {
-                    self.selected_idx = if selected_idx >= 5 { Some(selected_idx - 5) } else { Some(0) };
-                }
-                KeyCode::PageDown | KeyCode::Char('G') if !self.list.is_empty() => {
-                    self.selected_idx = if (selected_idx as isize) < (self.list.len() as isize - 5) {
-                        Some(selected_idx + 5)
-                    } else {
-                        Some(self.list.len() - 1)
-                    };
-                }
+                KeyCode::Char(c) => query.push(c),
+                KeyCode::Backspace if query.pop().is_none() => self.filter = None,
+                KeyCode::Backspace => {}
+                KeyCode::Esc => self.filter = None,
+                _ => return self.apply_navigation_event(code),
+            }
+            self.update_filter(true);
+        } else if code == KeyCode::Char('/') {
+            self.filter = Some(String::new());
+            self.update_filter(false);
+        } else {
+            self.apply_navigation_event(code);
+        }
+    }
 
-                KeyCode::Up | KeyCode::Char('k') if selected_idx > 0 => self.selected_idx = Some(selected_idx - 1),
-                KeyCode::Down | KeyCode::Char('j') if selected_idx < self.list.len() - 1 => {
-                    self.selected_idx = Some(selected_idx + 1)
-                }
+    fn apply_navigation_event(&mut self, code: KeyCode) {
+        if let (Some(selected_idx), Some(position)) = (self.selected_idx, self.selected_position()) {
+            match code {
+                KeyCode::PageUp | KeyCode::Char('g') => self.select_position(position.saturating_sub(5)),
+                KeyCode::PageDown | KeyCode::Char('G') => self.select_position(position + 5),
+
+                KeyCode::Up | KeyCode::Char('k') if position > 0 => self.select_position(position - 1),
+                KeyCode::Down | KeyCode::Char('j') => self.select_position(position + 1),
                 KeyCode::Char('u') => {
                     if let Some(entry) = self.recently_deleted.pop() {
                         self.list.push(entry);
                     }
                     self.selected_idx = Some(self.list.len() - 1);
+                    self.update_filter(false);
                 }
+                // bookmarks of shared collections are read-only
+                KeyCode::Delete | KeyCode::Backspace if self.list[selected_idx].bookmark.collection.is_some() => {}
                 KeyCode::Delete | KeyCode::Backspace => {
                     let deleted_entry = self.list.remove(selected_idx);
                     self.recently_deleted.push(deleted_entry);
-                    if self.list.is_empty() {
-                        self.selected_idx = None;
-                    } else if self.list.get(selected_idx).is_none() {
-                        self.selected_idx = Some(selected_idx - 1);
-                    }
+                    self.update_filter(false);
+                    self.select_position(position.min(self.visible.len().saturating_sub(1)));
                 }
 
                 _ => {}
@@ -58,3 +146,86 @@ impl CommandListState {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn list_state(commands: &[&str]) -> CommandListState {
+        CommandListState::new(commands.iter().map(|command| CommandEntry::new(vec![command.to_string()])).collect(), None)
+    }
+
+    fn type_keys(state: &mut CommandListState, keys: &str) {
+        for c in keys.chars() {
+            state.apply_event(KeyCode::Char(c));
+        }
+    }
+
+    fn visible_commands(state: &CommandListState) -> Vec<String> {
+        state.visible_entries().map(|(_, entry, _)| entry.as_string()).collect()
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut state = list_state(&["grep foo", "sed 's/a/b/'", "ls | grep bar", "cat"]);
+        assert_eq!(state.selected_idx, Some(3));
+        type_keys(&mut state, "/gr");
+        assert_eq!(visible_commands(&state), vec!["grep foo", "ls | grep bar"]);
+        assert_eq!(state.selected_idx, Some(2));
+
+        // navigation works on the filtered entries
+        state.apply_event(KeyCode::Up);
+        assert_eq!(state.selected_idx, Some(0));
+        state.apply_event(KeyCode::Up);
+        assert_eq!(state.selected_idx, Some(0));
+
+        type_keys(&mut state, "b");
+        assert_eq!(visible_commands(&state), vec!["ls | grep bar"]);
+        let (_, _, positions) = state.visible_entries().next().unwrap();
+        assert_eq!(positions, &[5, 6, 10]);
+
+        state.apply_event(KeyCode::Backspace);
+        state.apply_event(KeyCode::Esc);
+        assert_eq!(state.filter, None);
+        assert_eq!(visible_commands(&state).len(), 4);
+    }
+
+    #[test]
+    fn test_shared_collections() {
+        let mut shared = CommandEntry::new(vec!["kubectl get pods".to_string()]);
+        shared.bookmark.collection = Some("team".to_string());
+        let mut list = vec![shared.clone()];
+        list.extend(list_state(&["ls"]).list);
+        let mut state = CommandListState::new(list, Some(0));
+
+        // shared entries can't be deleted, but copied
+        state.apply_event(KeyCode::Delete);
+        assert_eq!(state.list.len(), 2);
+        state.copy_selected_to_personal();
+        assert_eq!(state.selected_idx, Some(2));
+        let personal = state.personal_entries().iter().map(|entry| entry.as_string()).collect::<Vec<_>>();
+        assert_eq!(personal, vec!["ls", "kubectl get pods"]);
+
+        // copying again selects the existing copy
+        state.select_position(0);
+        state.copy_selected_to_personal();
+        assert_eq!(state.selected_idx, Some(2));
+        assert_eq!(state.list.len(), 3);
+    }
+
+    #[test]
+    fn test_delete_while_filtering() {
+        let mut state = list_state(&["grep foo", "cat", "grep bar"]);
+        type_keys(&mut state, "/grep");
+        state.apply_event(KeyCode::Delete);
+        assert_eq!(visible_commands(&state), vec!["grep foo"]);
+        assert_eq!(state.selected_idx, Some(0));
+        assert_eq!(state.list.len(), 2);
+
+        // letters are part of the query, so undo is only available after closing the filter
+        state.apply_event(KeyCode::Esc);
+        state.apply_event(KeyCode::Char('u'));
+        assert_eq!(state.list.len(), 3);
+        assert_eq!(state.selected_entry().unwrap().as_string(), "grep bar");
+    }
+}
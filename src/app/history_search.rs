@@ -0,0 +1,114 @@
+use crate::commandlist::CommandEntry;
+use std::ops::Range;
+
+/// state of a bash-style reverse incremental search through the history
+#[derive(Debug, Clone, Default)]
+pub struct HistorySearchState {
+    pub query: String,
+    /// index of the history entry that currently matches the query, if any
+    pub match_idx: Option<usize>,
+}
+
+impl HistorySearchState {
+    pub fn new() -> HistorySearchState {
+        HistorySearchState::default()
+    }
+
+    pub fn push_char(&mut self, entries: &[CommandEntry], c: char) {
+        self.query.push(c);
+        // the current match is kept as long as it still contains the query
+        let search_end = self.match_idx.map(|idx| idx + 1).unwrap_or_else(|| entries.len());
+        self.match_idx = self.find_before(entries, search_end, None);
+    }
+
+    pub fn pop_char(&mut self, entries: &[CommandEntry]) {
+        self.query.pop();
+        self.match_idx = self.find_before(entries, entries.len(), None);
+    }
+
+    /// go to the next older entry matching the query, skipping duplicates of the current match.
+    /// If there is none, the current match is kept.
+    pub fn search_older(&mut self, entries: &[CommandEntry]) {
+        if let Some(idx) = self.match_idx {
+            if let Some(older_idx) = self.find_before(entries, idx, Some(&entries[idx])) {
+                self.match_idx = Some(older_idx);
+            }
+        }
+    }
+
+    pub fn matched_entry<'a>(&self, entries: &'a [CommandEntry]) -> Option<&'a CommandEntry> {
+        self.match_idx.and_then(|idx| entries.get(idx))
+    }
+
+    /// byte range of the query in the matched entry, with its lines joined by `\n`
+    pub fn match_range(&self, entries: &[CommandEntry]) -> Option<Range<usize>> {
+        let start = self.matched_entry(entries)?.as_string().find(&self.query)?;
+        Some(start..start + self.query.len())
+    }
+
+    fn find_before(&self, entries: &[CommandEntry], end: usize, skipped: Option<&CommandEntry>) -> Option<usize> {
+        if self.query.is_empty() {
+            return None;
+        }
+        entries[..end]
+            .iter()
+            .rposition(|entry| Some(entry) != skipped && entry.as_string().contains(&self.query))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(commands: &[&str]) -> Vec<CommandEntry> {
+        commands
+            .iter()
+            .map(|command| CommandEntry::new(command.split('\n').map(|line| line.to_string()).collect()))
+            .collect()
+    }
+
+    fn search(state: &mut HistorySearchState, entries: &[CommandEntry], query: &str) {
+        for c in query.chars() {
+            state.push_char(entries, c);
+        }
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let entries = entries(&["grep foo", "sed 's/a/b/'", "grep bar", "ls\n| grep baz", "cat"]);
+        let mut state = HistorySearchState::new();
+        assert_eq!(state.match_idx, None);
+        search(&mut state, &entries, "grep");
+        assert_eq!(state.match_idx, Some(3));
+        assert_eq!(state.match_range(&entries), Some(5..9));
+
+        // typing more keeps the current match while it still matches
+        search(&mut state, &entries, " b");
+        assert_eq!(state.match_idx, Some(3));
+        search(&mut state, &entries, "ar");
+        assert_eq!(state.match_idx, Some(2));
+        search(&mut state, &entries, "x");
+        assert_eq!(state.match_idx, None);
+
+        state.pop_char(&entries);
+        assert_eq!(state.match_idx, Some(2));
+        state.pop_char(&entries);
+        state.pop_char(&entries);
+        assert_eq!(state.match_idx, Some(3));
+    }
+
+    #[test]
+    fn test_search_older() {
+        let entries = entries(&["grep foo", "grep bar", "sort", "grep bar", "cat"]);
+        let mut state = HistorySearchState::new();
+        search(&mut state, &entries, "grep");
+        assert_eq!(state.match_idx, Some(3));
+        // duplicates of the current match are skipped
+        state.search_older(&entries);
+        assert_eq!(state.match_idx, Some(0));
+        // there is nothing older, so the match stays
+        state.search_older(&entries);
+        assert_eq!(state.match_idx, Some(0));
+        assert_eq!(state.matched_entry(&entries).unwrap().as_string(), "grep foo");
+    }
+}
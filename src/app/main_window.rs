@@ -1,10 +1,15 @@
-use crate::lineeditor::{ EditorEvent, convert_keyevent_to_editorevent };
+use crate::keybindings::Action;
+use crate::lineeditor::EditorEvent;
 use crate::CmdOutput;
 use crate::Stdio;
 use crate::app::key_select_menu::KeySelectMenu;
+use crate::shell_lexer;
 use crate::util::{StringExt, VecStringExt};
 use crate::app::app::{App, KeySelectMenuType, CachedCommandPart};
+use crate::app::history_search::HistorySearchState;
+use crate::vi_mode::ViMode;
 
+use itertools::Itertools;
 use std::path::Path;
 use crossterm::event::{KeyCode, KeyModifiers};
 use std::path::PathBuf;
@@ -70,7 +75,12 @@ impl App {
     }
 
     fn do_cache_command_part(&mut self) {
-        if self.input_state.hovered_char() != Some("|") {
+        let content_lines = self.input_state.content_lines();
+        let cursor_offset = content_lines.joined_offset_of(self.input_state.cursor_line, self.input_state.cursor_col);
+        let is_on_pipe = shell_lexer::pipeline_stages(&content_lines.join("\n"))
+            .iter()
+            .any(|stage| stage.pipe_offset == Some(cursor_offset));
+        if !is_on_pipe {
             return;
         }
         let mut command_to_cache = self
@@ -84,10 +94,17 @@ impl App {
             command_to_cache.pop();
         }
 
+        let command_to_cache = shell_lexer::strip_comments(&command_to_cache.join("\n")).lines().join(" ");
         let command_result = self
             .execution_handler
             .execution_mode
-            .run_cmd_blocking(&self.execution_handler.eval_environment, &command_to_cache.join(" "));
+            .run_cmd_blocking(
+                &self.execution_handler.eval_environment,
+                &self.execution_handler.resource_limits,
+                &command_to_cache,
+                self.input_data(),
+                None,
+            );
 
         if self.input_state.cursor_col < self.input_state.current_line().len() {
             match command_result {
@@ -143,8 +160,6 @@ impl App {
     }
 
     pub async fn handle_main_window_tui_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
-        let control_pressed = modifiers.contains(KeyModifiers::CONTROL);
-
         if let Some(autocomplete_state) = self.autocomplete_state.as_mut() {
             match code {
                 KeyCode::Tab | KeyCode::Down => autocomplete_state.cycle_selected(),
@@ -168,56 +183,188 @@ impl App {
             return;
         }
 
-        match code {
-            KeyCode::Esc => self.set_should_quit(),
-            KeyCode::Char('q') | KeyCode::Char('c') if control_pressed => self.set_should_quit(),
-            KeyCode::F(2) => self.autoeval_mode = !self.autoeval_mode,
-            KeyCode::F(3) => self.paranoid_history_mode = !self.paranoid_history_mode,
-            KeyCode::Tab => self.open_autocomplete_menu(),
-            KeyCode::F(5) => self.open_helpviewer(),
-            KeyCode::F(6) => self.open_outputviewer(),
-            KeyCode::F(7) => self.do_cache_command_part(),
-
-            KeyCode::Char('s') if control_pressed => self.bookmarks.toggle_entry(self.input_state.content_to_commandentry()),
-            KeyCode::Char('p') if control_pressed => self.apply_history_prev(),
-            KeyCode::Char('n') if control_pressed => self.apply_history_next(),
-            KeyCode::Char('x') if control_pressed => {
+        if self.sample_input_focused {
+            self.handle_sample_input_event(code, modifiers).await;
+            return;
+        }
+
+        if self.history_search.is_some() {
+            let key_was_handled = self.handle_history_search_event(code, modifiers).await;
+            if key_was_handled {
+                return;
+            }
+        }
+
+        let in_vi_normal_mode = matches!(self.vi_state.as_ref().map(|vi_state| vi_state.mode), Some(ViMode::Normal) | Some(ViMode::Visual));
+        let action = match self.config.keybindings.action(code, modifiers) {
+            // in vi mode, Esc is needed to leave insert mode
+            _ if self.vi_state.is_some() && code == KeyCode::Esc => None,
+            // Ctrl+R is redo in vi normal mode
+            Some(Action::HistorySearch) if in_vi_normal_mode && code == KeyCode::Char('r') => None,
+            action => action,
+        };
+
+        match action {
+            Some(Action::Quit) => self.set_should_quit(),
+            Some(Action::ToggleAutoeval) => self.autoeval_mode = !self.autoeval_mode,
+            Some(Action::ToggleParanoidHistory) => self.paranoid_history_mode = !self.paranoid_history_mode,
+            Some(Action::Autocomplete) => self.open_autocomplete_menu(),
+            Some(Action::OpenHelpViewer) => self.open_helpviewer(),
+            Some(Action::OpenOutputViewer) => self.open_outputviewer(),
+            Some(Action::CacheCommandPart) => self.do_cache_command_part(),
+            Some(Action::ToggleSampleInput) => self.focus_sample_input().await,
+            Some(Action::CancelExecution) => self.cancel_execution().await,
+
+            Some(Action::SaveBookmark) => self.bookmarks.toggle_entry(self.input_state.content_to_commandentry()),
+            Some(Action::HistoryPrev) => self.apply_history_prev(),
+            Some(Action::HistoryNext) => self.apply_history_next(),
+            Some(Action::HistorySearch) => self.history_search = Some(HistorySearchState::new()),
+            Some(Action::ClearToHistory) => {
                 self.history.push(self.input_state.content_to_commandentry());
                 self.history_idx = None;
                 self.input_state.apply_event(EditorEvent::Clear);
             }
 
-            KeyCode::Char('v') if control_pressed => {
+            Some(Action::InsertSnippet) => {
                 self.opened_key_select_menu = Some(KeySelectMenu::new(
                     self.config.snippets.iter().map(|(&c, v)| (c, v.to_string())).collect(),
                     KeySelectMenuType::Snippets,
                 ));
             }
-            KeyCode::Enter if !modifiers.contains(KeyModifiers::ALT) => {
+            Some(Action::Execute) => {
                 self.history.push(self.input_state.content_to_commandentry());
                 self.execute_content().await;
             }
 
             _ => {
-                if let Some(editor_event) = convert_keyevent_to_editorevent(code, modifiers) {
-                    let previous_content = self.input_state.content_lines().clone();
-                    self.history_idx = None;
+                let previous_content = self.input_state.content_lines().clone();
+                let handled = if let Some(vi_state) = self.vi_state.as_mut() {
+                    vi_state.handle_key(&mut self.input_state, &self.config.keybindings, code, modifiers);
+                    true
+                } else if let Some(editor_event) = self.config.keybindings.editor_event(code, modifiers) {
                     self.input_state.apply_event(editor_event);
+                    true
+                } else {
+                    false
+                };
 
-                    let new_content = self.input_state.content_lines();
-
-                    if let Some(CachedCommandPart { end_line, end_col, .. }) = self.cached_command_part {
-                        if previous_content.split_strings_at_offset(end_line, end_col).0
-                            != new_content.split_strings_at_offset(end_line, end_col).0
-                        {
-                            self.cached_command_part = None;
-                        }
+                if let Some(copied_text) = self.input_state.take_copied_text() {
+                    if self.config.osc52_clipboard {
+                        self.pending_clipboard = Some(copied_text);
                     }
+                }
 
-                    if self.autoeval_mode && previous_content != *new_content {
-                        self.execute_content().await;
-                    }
+                if handled {
+                    self.on_command_edited(previous_content).await;
+                }
+            }
+        }
+    }
+
+    /// handle a key while searching the history. Keys that don't edit the search accept the match,
+    /// returns false if the key should then be handled as usual.
+    async fn handle_history_search_event(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let search = match self.history_search.as_mut() {
+            Some(search) => search,
+            None => return false,
+        };
+        let entries = self.history.entries();
+        let is_plain_key = !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        match (code, self.config.keybindings.action(code, modifiers)) {
+            (_, Some(Action::HistorySearch)) => search.search_older(entries),
+            (_, Some(Action::CancelExecution)) | (KeyCode::Esc, _) => self.history_search = None,
+            (KeyCode::Backspace, _) => search.pop_char(entries),
+            (KeyCode::Char(c), _) if is_plain_key => search.push_char(entries, c),
+            _ => {
+                let search = self.history_search.take().unwrap();
+                if let Some(entry) = search.matched_entry(self.history.entries()).cloned() {
+                    let previous_content = self.input_state.content_lines().clone();
+                    self.input_state.load_commandentry(&entry);
+                    self.cached_command_part = None;
+                    self.on_command_edited(previous_content).await;
                 }
+                return code == KeyCode::Enter;
+            }
+        }
+        true
+    }
+
+    /// forget the cached command part if the edit changed it, and run the command again in autoeval mode
+    async fn on_command_edited(&mut self, previous_content: Vec<String>) {
+        self.history_idx = None;
+        let new_content = self.input_state.content_lines();
+
+        if let Some(CachedCommandPart { end_line, end_col, .. }) = self.cached_command_part {
+            if previous_content.split_strings_at_offset(end_line, end_col).0
+                != new_content.split_strings_at_offset(end_line, end_col).0
+            {
+                self.cached_command_part = None;
+            }
+        }
+
+        if self.autoeval_mode && previous_content != *new_content {
+            self.schedule_autoeval().await;
+        }
+    }
+
+    /// insert pasted text into the focused editor or the Ctrl+R search, without treating line breaks as Enter presses.
+    /// Returns false if there is nothing to paste into, like while a menu is open.
+    pub async fn paste_into_main_window(&mut self, text: &str) -> bool {
+        if let Some(search) = self.history_search.as_mut() {
+            // the query is a single line
+            for c in text.chars() {
+                search.push_char(self.history.entries(), if c == '\n' || c == '\r' { ' ' } else { c });
+            }
+        } else if self.autocomplete_state.is_some() || self.opened_key_select_menu.is_some() {
+            return false;
+        } else if self.sample_input_focused {
+            self.sample_input_state.apply_event(EditorEvent::InsertText(text.to_string()));
+            self.cached_command_part = None;
+            if self.autoeval_mode {
+                self.schedule_autoeval().await;
+            }
+        } else {
+            let previous_content = self.input_state.content_lines().clone();
+            self.input_state.apply_event(EditorEvent::InsertText(text.to_string()));
+            self.on_command_edited(previous_content).await;
+        }
+        true
+    }
+
+    async fn focus_sample_input(&mut self) {
+        self.sample_input_focused = true;
+        if !self.sample_input_open {
+            self.sample_input_open = true;
+            self.cached_command_part = None;
+            if self.autoeval_mode {
+                self.schedule_autoeval().await;
+            }
+        }
+    }
+
+    async fn handle_sample_input_event(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        let previous_content = self.sample_input_state.content_lines().clone();
+        match (code, self.config.keybindings.action(code, modifiers)) {
+            (KeyCode::Esc, _) => {
+                self.sample_input_open = false;
+                self.sample_input_focused = false;
+            }
+            (KeyCode::Enter, _) => self.sample_input_state.apply_event(EditorEvent::NewLine),
+            (_, Some(Action::ToggleSampleInput)) => self.sample_input_focused = false,
+            (_, Some(Action::Quit)) => self.set_should_quit(),
+            _ => {
+                if let Some(editor_event) = self.config.keybindings.editor_event(code, modifiers) {
+                    self.sample_input_state.apply_event(editor_event);
+                }
+            }
+        }
+
+        // the sample input changed what is piped into the command
+        let input_changed = previous_content != *self.sample_input_state.content_lines() || !self.sample_input_open;
+        if input_changed {
+            self.cached_command_part = None;
+            if self.autoeval_mode {
+                self.schedule_autoeval().await;
             }
         }
     }
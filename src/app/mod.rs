@@ -1,6 +1,10 @@
 extern crate crossterm;
 
 pub mod app;
+pub mod bookmark_editor;
 pub mod command_list_window;
+pub mod history_search;
 pub mod key_select_menu;
 pub mod main_window;
+pub mod stage_inspector;
+pub mod variable_form;
@@ -0,0 +1,107 @@
+use crate::command_evaluation::{CancelHandle, ExecutionMode};
+use crate::resource_limits::ResourceLimits;
+use crate::shell_lexer;
+use crossterm::event::KeyCode;
+use itertools::Itertools;
+use std::sync::mpsc::{self, Receiver};
+
+/// The result of running the pipeline up to (and including) a single stage
+pub struct StageResult {
+    /// the command of this stage on its own, without the stages before it
+    pub stage_command: String,
+    /// the output lines of everything up to this stage, or an error message.
+    /// None while the stage is still running.
+    pub output: Option<Result<Vec<String>, String>>,
+}
+
+pub struct StageInspectorState {
+    pub stages: Vec<StageResult>,
+    /// index of the leftmost stage that is currently shown
+    pub scroll_offset: usize,
+    /// the outputs of the stages, sent by the thread running them
+    results: Receiver<(usize, Result<Vec<String>, String>)>,
+    cancel_handle: CancelHandle,
+}
+
+impl StageInspectorState {
+    /// start running every prefix of the given pipeline, split at its top-level `|` symbols, with the given stdin piped
+    /// into each. The stages run on a separate thread, their outputs are collected by `receive_results`.
+    pub fn inspect(
+        lines: &[String],
+        execution_mode: &ExecutionMode,
+        eval_environment: &[String],
+        resource_limits: &ResourceLimits,
+        stdin: Option<&[String]>,
+        raw_mode: bool,
+    ) -> StageInspectorState {
+        let command = shell_lexer::strip_comments(&lines.join("\n"));
+        let command = if raw_mode { command } else { command.lines().join(" ") };
+
+        let spans = shell_lexer::pipeline_stages(&command)
+            .into_iter()
+            .map(|stage| stage.span)
+            .filter(|span| !span.is_empty())
+            .collect_vec();
+        let stages = spans
+            .iter()
+            .map(|span| StageResult {
+                stage_command: command[span.clone()].to_string(),
+                output: None,
+            })
+            .collect();
+
+        let (result_send, results) = mpsc::channel();
+        let cancel_handle = CancelHandle::default();
+        let (execution_mode, eval_environment, resource_limits) =
+            (execution_mode.clone(), eval_environment.to_vec(), resource_limits.clone());
+        let (stdin, thread_cancel_handle) = (stdin.map(|stdin| stdin.to_vec()), cancel_handle.clone());
+        std::thread::spawn(move || {
+            for (idx, span) in spans.into_iter().enumerate() {
+                let output = execution_mode.run_cmd_blocking(
+                    &eval_environment,
+                    &resource_limits,
+                    &command[..span.end],
+                    stdin.as_deref(),
+                    Some(&thread_cancel_handle),
+                );
+                // the inspector was closed
+                if result_send.send((idx, output)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        StageInspectorState {
+            stages,
+            scroll_offset: 0,
+            results,
+            cancel_handle,
+        }
+    }
+
+    /// store the outputs of the stages that finished since the last call
+    pub fn receive_results(&mut self) {
+        for (idx, output) in self.results.try_iter() {
+            self.stages[idx].output = Some(output);
+        }
+    }
+
+    /// kill the running stage, and don't run the remaining ones
+    pub fn cancel(&self) {
+        self.cancel_handle.cancel();
+    }
+
+    pub fn apply_event(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Left | KeyCode::Char('h') if self.scroll_offset > 0 => self.scroll_offset -= 1,
+            KeyCode::Right | KeyCode::Char('l') if self.scroll_offset + 1 < self.stages.len() => self.scroll_offset += 1,
+            _ => {}
+        }
+    }
+}
+
+impl Drop for StageInspectorState {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
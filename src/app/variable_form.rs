@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use crate::app::command_list_window::CommandListState;
+use crate::command_template::{substitute_variables, template_variables, TemplateVariable};
+use crate::commandlist::CommandEntry;
+use crate::lineeditor::{EditorEvent, EditorState};
+
+/// a form to fill in the variables of a bookmark before it is loaded
+pub struct VariableFormState {
+    /// the bookmark list the form was opened from, which is shown again if it's cancelled
+    list_state: CommandListState,
+    entry: CommandEntry,
+    pub variables: Vec<TemplateVariable>,
+    /// one field per variable, prefilled with its default
+    pub fields: Vec<EditorState>,
+    pub focused_field: usize,
+}
+
+impl VariableFormState {
+    /// open the form for the selected bookmark, if it has any variables
+    pub fn open(list_state: CommandListState) -> Result<VariableFormState, CommandListState> {
+        let entry = match list_state.selected_entry() {
+            Some(entry) => entry.clone(),
+            None => return Err(list_state),
+        };
+        let variables = template_variables(&entry.as_string());
+        if variables.is_empty() {
+            return Err(list_state);
+        }
+        let fields = variables
+            .iter()
+            .map(|variable| {
+                let mut field = EditorState::new();
+                field.set_content(vec![variable.default.clone().unwrap_or_default()]);
+                field
+            })
+            .collect();
+        Ok(VariableFormState {
+            list_state,
+            entry,
+            variables,
+            fields,
+            focused_field: 0,
+        })
+    }
+
+    pub fn focus_next(&mut self) {
+        self.focused_field = (self.focused_field + 1) % self.fields.len();
+    }
+
+    pub fn focus_previous(&mut self) {
+        self.focused_field = (self.focused_field + self.fields.len() - 1) % self.fields.len();
+    }
+
+    /// edit the focused field. The fields are single lines, so newlines are not inserted.
+    pub fn apply_editor_event(&mut self, event: EditorEvent) {
+        let event = match event {
+            EditorEvent::NewLine => return,
+            EditorEvent::InsertText(text) => EditorEvent::InsertText(text.replace('\n', " ")),
+            event => event,
+        };
+        self.fields[self.focused_field].apply_event(event);
+    }
+
+    /// the lines of the bookmark, with the variables replaced by the entered values
+    pub fn resolved_lines(&self) -> Vec<String> {
+        let values = self
+            .variables
+            .iter()
+            .zip(&self.fields)
+            .map(|(variable, field)| (variable.name.clone(), field.content_str()))
+            .collect::<HashMap<_, _>>();
+        substitute_variables(&self.entry.as_string(), &values)
+            .split('\n')
+            .map(|line| line.to_owned())
+            .collect()
+    }
+
+    /// return to the bookmark list
+    pub fn cancel(self) -> CommandListState {
+        self.list_state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fill_in_variables() {
+        let entries = vec![
+            CommandEntry::new(vec!["ls".to_string()]),
+            CommandEntry::new(vec!["cat {{?file:log.txt}}".to_string(), "| grep {{?pattern}}".to_string()]),
+        ];
+        assert!(VariableFormState::open(CommandListState::new(entries.clone(), Some(0))).is_err());
+
+        let mut state = VariableFormState::open(CommandListState::new(entries, Some(1))).ok().unwrap();
+        assert_eq!(state.resolved_lines(), vec!["cat log.txt", "| grep "]);
+        state.focus_previous();
+        for c in "err".chars() {
+            state.apply_editor_event(EditorEvent::NewCharacter(c));
+        }
+        state.apply_editor_event(EditorEvent::InsertText("or\nx".to_string()));
+        assert_eq!(state.resolved_lines(), vec!["cat log.txt", "| grep error x"]);
+    }
+}
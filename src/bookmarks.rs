@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::commandlist::CommandEntry;
+
+/// the name, description and tags given to a bookmarked command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookmarkInfo {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    /// the name of the shared collection this bookmark was loaded from. Personal bookmarks have none.
+    pub collection: Option<String>,
+}
+
+impl BookmarkInfo {
+    /// the name and tags, as shown in front of the command in the bookmark list
+    pub fn label(&self) -> String {
+        std::iter::once(self.name.clone())
+            .chain(self.tags.iter().map(|tag| format!("#{}", tag)))
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// read tags separated by commas or whitespace, ignoring leading `#`s
+    pub fn parse_tags(text: &str) -> Vec<String> {
+        text.split(|c: char| c == ',' || c.is_whitespace())
+            .map(|tag| tag.trim_start_matches('#'))
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| tag.to_owned())
+            .collect()
+    }
+}
+
+/// the structure of the bookmarks.toml file
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BookmarkFile {
+    bookmark: Vec<StoredBookmark>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoredBookmark {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    command: String,
+}
+
+pub fn serialize_bookmarks(entries: &[CommandEntry]) -> String {
+    let file = BookmarkFile {
+        bookmark: entries
+            .iter()
+            .map(|entry| StoredBookmark {
+                name: entry.bookmark.name.clone(),
+                description: entry.bookmark.description.clone(),
+                tags: entry.bookmark.tags.clone(),
+                command: entry.as_string(),
+            })
+            .collect(),
+    };
+    // serializing plain strings and lists of them can't fail
+    toml::to_string(&file).expect("could not serialize bookmarks")
+}
+
+pub fn deserialize_bookmarks(contents: &str) -> Result<Vec<CommandEntry>, String> {
+    let file: BookmarkFile = toml::from_str(contents).map_err(|err| format!("invalid bookmarks file: {}", err))?;
+    Ok(file
+        .bookmark
+        .into_iter()
+        .filter(|bookmark| !bookmark.command.is_empty())
+        .map(|bookmark| {
+            let mut entry = CommandEntry::new(bookmark.command.lines().map(|line| line.to_owned()).collect());
+            entry.bookmark = BookmarkInfo {
+                name: bookmark.name,
+                description: bookmark.description,
+                tags: bookmark.tags,
+                collection: None,
+            };
+            entry
+        })
+        .collect())
+}
+
+/// load the bookmarks of the shared collections. Each path is either a collection file,
+/// or a directory whose `.toml` files are collections. Collections are named after their files.
+/// Collections that can't be read are skipped, and the errors returned alongside the bookmarks.
+pub fn load_collections(paths: &[PathBuf]) -> (Vec<CommandEntry>, Vec<String>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let files = if path.is_dir() {
+            let dir_entries = match fs::read_dir(path) {
+                Ok(dir_entries) => dir_entries,
+                Err(err) => {
+                    errors.push(format!("could not read {}: {}", path.display(), err));
+                    continue;
+                }
+            };
+            let mut files = dir_entries
+                .filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+                .filter(|file| file.extension().is_some_and(|ext| ext == "toml"))
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        } else {
+            vec![path.clone()]
+        };
+        for file in files {
+            let name = file.file_stem().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            let collection = fs::read_to_string(&file)
+                .map_err(|err| format!("could not read {}: {}", file.display(), err))
+                .and_then(|contents| deserialize_bookmarks(&contents).map_err(|err| format!("{}: {}", file.display(), err)));
+            let collection = match collection {
+                Ok(collection) => collection,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+            entries.extend(collection.into_iter().map(|mut entry| {
+                entry.bookmark.collection = Some(name.clone());
+                entry
+            }));
+        }
+    }
+    (entries, errors)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut named = CommandEntry::new(vec!["ps aux".to_string(), "| grep \"x\"".to_string()]);
+        named.bookmark = BookmarkInfo {
+            name: "find process".to_string(),
+            description: "look for a process by name".to_string(),
+            tags: vec!["ps".to_string(), "system".to_string()],
+            collection: None,
+        };
+        let plain = CommandEntry::new(vec!["ls".to_string()]);
+
+        let read = deserialize_bookmarks(&serialize_bookmarks(&[named.clone(), plain.clone()])).unwrap();
+        assert_eq!(read, vec![named.clone(), plain]);
+        assert_eq!(read[0].bookmark, named.bookmark);
+        assert_eq!(read[1].bookmark, BookmarkInfo::default());
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        assert!(deserialize_bookmarks("[[bookmark]]\nname = \"x\"").is_err());
+        assert!(deserialize_bookmarks("[[bookmark]]\ncommand = \"ls\"\nunknown = 1").is_err());
+        assert_eq!(deserialize_bookmarks("").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn test_load_collections() {
+        let dir = std::env::temp_dir().join(format!("pipr-test-collections-{}", std::process::id()));
+        fs::create_dir_all(dir.join("team")).unwrap();
+        fs::write(dir.join("team/logs.toml"), "[[bookmark]]\nname = \"errors\"\ncommand = \"grep ERROR\"").unwrap();
+        fs::write(dir.join("team/k8s.toml"), "[[bookmark]]\ncommand = \"kubectl get pods | grep -v Running\"").unwrap();
+        fs::write(dir.join("team/notes.txt"), "not a collection").unwrap();
+        fs::write(dir.join("single.toml"), "[[bookmark]]\ncommand = \"ls\"").unwrap();
+        fs::write(dir.join("broken.toml"), "[[bookmark]]\nname = \"no command\"").unwrap();
+
+        let paths = [dir.join("team"), dir.join("missing.toml"), dir.join("broken.toml"), dir.join("single.toml")];
+        let (entries, errors) = load_collections(&paths);
+        let collections = entries.iter().map(|entry| entry.bookmark.collection.clone().unwrap()).collect::<Vec<_>>();
+        assert_eq!(collections, vec!["k8s", "logs", "single"]);
+        assert_eq!(entries[1].bookmark.name, "errors");
+
+        // the collections that can't be loaded are skipped
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("could not read"));
+        assert!(errors[1].contains("broken.toml"));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(BookmarkInfo::parse_tags(" #a, b c,,"), vec!["a", "b", "c"]);
+        let info = BookmarkInfo {
+            name: "name".to_string(),
+            description: String::new(),
+            tags: vec!["a".to_string(), "b".to_string()],
+            collection: None,
+        };
+        assert_eq!(info.label(), "name #a #b");
+    }
+}
@@ -1,18 +1,27 @@
 use futures::future::Either::*;
 use futures::stream::StreamExt;
+use std::fmt::{self, Display, Formatter};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::process::Stdio;
-use std::{io::BufRead, str, time::Duration};
-use tokio::io::{self, AsyncBufReadExt};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io::BufRead, str};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt};
 use tokio::prelude::*;
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
-const BUBBLEWRAP_ARGS: &str = "--ro-bind / / --tmpfs /tmp --dev /dev --proc /proc --die-with-parent --share-net --unshare-pid";
+use crate::command_policy::CommandPolicy;
+use crate::resource_limits::ResourceLimits;
+use crate::sandbox::SandboxConfig;
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+/// how often the stdout of a still running command is sent to the ui
+const PARTIAL_OUTPUT_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum ExecutionMode {
-    UNSAFE,
-    ISOLATED,
+    UNSAFE(CommandPolicy),
+    ISOLATED(SandboxConfig),
 }
 
 /// Represents a command that should be executed, and an optional stdin that should be piped into it
@@ -27,31 +36,150 @@ impl CommandExecutionRequest {
     }
 }
 
+/// Messages sent to the thread of a CommandExecutionHandler
+enum ExecutorMessage {
+    Execute(CommandExecutionRequest),
+    /// kill the currently running command
+    Cancel,
+}
+
 pub struct CommandExecutionHandler {
     pub execution_mode: ExecutionMode,
     pub eval_environment: Vec<String>,
     pub cmd_timeout: Duration,
+    pub resource_limits: ResourceLimits,
     pub cmd_out_receive: Receiver<CmdOutput>,
-    cmd_in_send: Sender<CommandExecutionRequest>,
+    cmd_in_send: Sender<ExecutorMessage>,
     stop_send: Sender<()>,
 }
 
 pub enum CmdOutput {
-    Ok(String),
+    /// a chunk of stdout lines the still running command has produced since the last chunk
+    Partial(String),
+    /// the command has stopped running
+    Finished(CmdResult),
+    /// the command could not be run
     NotOk(String),
 }
 
+/// describes how a command stopped running
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum RunStatus {
+    Exited(i32),
+    Signaled(i32),
+    TimedOut,
+    Cancelled,
+    /// the command was killed because it's output exceeded the configured `max_output_bytes`
+    OutputTruncated,
+}
+
+impl RunStatus {
+    pub fn success(&self) -> bool {
+        *self == RunStatus::Exited(0)
+    }
+}
+
+impl From<std::process::ExitStatus> for RunStatus {
+    fn from(status: std::process::ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => RunStatus::Exited(code),
+            (None, Some(signal)) => RunStatus::Signaled(signal),
+            (None, None) => RunStatus::Exited(-1),
+        }
+    }
+}
+
+impl Display for RunStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RunStatus::Exited(code) => write!(f, "exit {}", code),
+            RunStatus::Signaled(signal) => write!(f, "signal {}", signal),
+            RunStatus::TimedOut => write!(f, "timed out"),
+            RunStatus::Cancelled => write!(f, "cancelled"),
+            RunStatus::OutputTruncated => write!(f, "output truncated"),
+        }
+    }
+}
+
+impl str::FromStr for RunStatus {
+    type Err = String;
+    /// parse a status as it is displayed
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid run status `{}`", s);
+        match s {
+            "timed out" => Ok(RunStatus::TimedOut),
+            "cancelled" => Ok(RunStatus::Cancelled),
+            "output truncated" => Ok(RunStatus::OutputTruncated),
+            _ => match s.split_once(' ') {
+                Some(("exit", code)) => code.parse().map(RunStatus::Exited).map_err(|_| invalid()),
+                Some(("signal", signal)) => signal.parse().map(RunStatus::Signaled).map_err(|_| invalid()),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// Metadata about a single run of a command
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct RunSummary {
+    pub status: RunStatus,
+    pub duration: Duration,
+    pub stdout_bytes: usize,
+    pub stdout_lines: usize,
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let duration = if self.duration < Duration::from_secs(1) {
+            format!("{}ms", self.duration.as_millis())
+        } else {
+            format!("{:.2}s", self.duration.as_secs_f64())
+        };
+        write!(
+            f,
+            "{} | {} | {} lines | {} bytes",
+            self.status, duration, self.stdout_lines, self.stdout_bytes
+        )
+    }
+}
+
+/// The output of a command that has stopped running
+#[derive(Debug, Clone)]
+pub struct CmdResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub summary: RunSummary,
+}
+
+impl CmdResult {
+    pub fn new(stdout: String, stderr: String, status: RunStatus, duration: Duration) -> CmdResult {
+        let summary = RunSummary {
+            status,
+            duration,
+            stdout_bytes: stdout.len(),
+            stdout_lines: stdout.lines().count(),
+        };
+        CmdResult { stdout, stderr, summary }
+    }
+}
+
 impl CommandExecutionHandler {
     /// start a CommandExecutionHandler thread.
-    pub fn start(cmd_timeout: Duration, execution_mode: ExecutionMode, eval_environment: Vec<String>) -> CommandExecutionHandler {
-        let (cmd_in_send, mut cmd_in_receive) = mpsc::channel::<CommandExecutionRequest>(10);
+    pub fn start(
+        cmd_timeout: Duration,
+        resource_limits: ResourceLimits,
+        execution_mode: ExecutionMode,
+        eval_environment: Vec<String>,
+    ) -> CommandExecutionHandler {
+        let (cmd_in_send, mut cmd_in_receive) = mpsc::channel::<ExecutorMessage>(10);
         let (mut cmd_out_send, cmd_out_receive) = mpsc::channel::<CmdOutput>(10);
         let (stop_send, mut stop_receive) = mpsc::channel::<()>(10);
 
         let executor = CommandExecutionHandler {
             eval_environment: eval_environment.clone(),
             cmd_timeout,
-            execution_mode,
+            resource_limits: resource_limits.clone(),
+            execution_mode: execution_mode.clone(),
             cmd_in_send,
             cmd_out_receive,
             stop_send,
@@ -64,12 +192,52 @@ impl CommandExecutionHandler {
             let mut out_lines = String::new();
             let mut err_lines = String::new();
 
+            // stdout lines that have not yet been sent as a partial output chunk
+            let mut pending_out_lines = String::new();
+            let mut partial_flush_interval = tokio::time::interval(PARTIAL_OUTPUT_INTERVAL);
+            let mut started_at = Instant::now();
+            // process id of the currently running command, which is also the id of its process group
+            let mut running_pid = None;
+            // whether the running command has been killed because it's output got too large
+            let mut output_truncated = false;
+
             loop {
                 tokio::select! {
-                    Some(new_cmd) = cmd_in_receive.recv() => {
-                        let child = execution_mode.run_cmd_tokio(&eval_environment, &new_cmd.command);
+                    Some(message) = cmd_in_receive.recv() => {
+                        // only the most recent message matters, every queued request before it has been superseded
+                        let mut message = message;
+                        while let Ok(newer_message) = cmd_in_receive.try_recv() {
+                            message = newer_message;
+                        }
+
+                        let new_cmd = match message {
+                            ExecutorMessage::Execute(new_cmd) => new_cmd,
+                            ExecutorMessage::Cancel => {
+                                if let Some(pid) = running_pid.take() {
+                                    kill_process_group(pid);
+                                    let result = CmdResult::new(out_lines, err_lines, RunStatus::Cancelled, started_at.elapsed());
+                                    cmd_out_send.send(CmdOutput::Finished(result)).await.ok().unwrap();
+
+                                    handle = Left(futures::future::pending());
+                                    out_lines_stream = Left(futures::stream::pending());
+                                    err_lines_stream = Left(futures::stream::pending());
+                                    out_lines = String::new();
+                                    err_lines = String::new();
+                                    pending_out_lines = String::new();
+                                }
+                                continue;
+                            }
+                        };
+
+                        // a new request supersedes the command that is still running
+                        if let Some(pid) = running_pid.take() {
+                            kill_process_group(pid);
+                        }
+
+                        let child = execution_mode.run_cmd_tokio(&eval_environment, &resource_limits, &new_cmd.command);
                         match child {
                             Ok(mut child) =>  {
+                                running_pid = Some(child.id());
 
                                 // this need's improving or at least some more testing
                                 if let Some(stdin_content) = new_cmd.stdin {
@@ -85,6 +253,9 @@ impl CommandExecutionHandler {
                                 err_lines_stream = Right(io::BufReader::new(child.stderr.take().unwrap()).lines());
                                 out_lines = String::new();
                                 err_lines = String::new();
+                                pending_out_lines = String::new();
+                                output_truncated = false;
+                                started_at = Instant::now();
                                 handle = Right(tokio::time::timeout(cmd_timeout, child));
                             }
                             Err(err) => cmd_out_send.send(CmdOutput::NotOk(err)).await.ok().unwrap(),
@@ -93,7 +264,20 @@ impl CommandExecutionHandler {
 
                     Some(line) = out_lines_stream.next() => {
                         match line {
-                            Ok(line) => out_lines.push_str(&(line + "\n")),
+                            Ok(line) => {
+                                let previous_len = out_lines.len();
+                                let line_fits = resource_limits.push_line(&mut out_lines, &line);
+                                pending_out_lines.push_str(&out_lines[previous_len..]);
+                                if !line_fits {
+                                    // stop the command, everything it outputs from now on would be discarded anyways
+                                    if let Some(pid) = running_pid {
+                                        kill_process_group(pid);
+                                    }
+                                    output_truncated = true;
+                                    out_lines_stream = Left(futures::stream::pending());
+                                    err_lines_stream = Left(futures::stream::pending());
+                                }
+                            }
                             Err(err) => {
                                 cmd_out_send.send(CmdOutput::NotOk(format!("Error: {}", err))).await.ok().unwrap();
                                 handle = Left(futures::future::pending());
@@ -102,7 +286,9 @@ impl CommandExecutionHandler {
                     }
                     Some(line) = err_lines_stream.next() => {
                         match line {
-                            Ok(line) => err_lines.push_str(&(line + "\n")),
+                            Ok(line) => {
+                                resource_limits.push_line(&mut err_lines, &line);
+                            }
                             Err(err) => {
                                 cmd_out_send.send(CmdOutput::NotOk(format!("Error: {}", err))).await.ok().unwrap();
                                 handle = Left(futures::future::pending());
@@ -111,50 +297,56 @@ impl CommandExecutionHandler {
                     }
 
                     result = &mut handle => {
-                        // resulting_output contains the command's output if everything went well,
-                        // stderr if it exited non-zero, and if any other error occured information about that.
+                        // resulting_output contains the command's output together with how it exited,
+                        // or information about the error if the command could not be awaited.
                         let resulting_output = match result {
-                            Ok(Ok(result)) => {
-                                if result.success() {
-                                    if let Right(stream) = out_lines_stream {
-                                        let results = stream.collect::<Vec<Result<_, _>>>().await.into_iter().collect::<Result<Vec<_>, _>>();
-                                        match results {
-                                            Ok(pending_lines) => {
-                                                out_lines.push_str(&pending_lines.join("\n"));
-                                                CmdOutput::Ok(out_lines)
-                                            }
-                                            Err(err) => CmdOutput::NotOk(format!("{}", err)),
-                                        }
-                                    } else {
-                                        CmdOutput::Ok(out_lines)
-                                    }
-                                } else {
-                                    if let Right(stream) = err_lines_stream {
-                                        let results = stream.collect::<Vec<Result<_, _>>>().await.into_iter().collect::<Result<Vec<_>, _>>();
-                                        match results {
-                                            Ok(pending_lines) => {
-                                                err_lines.push_str(&pending_lines.join("\n"));
-                                                CmdOutput::NotOk(err_lines)
-                                            }
-                                            Err(err) => CmdOutput::NotOk(format!("{}", err)),
-                                        }
-                                    } else {
-                                        CmdOutput::NotOk(err_lines)
+                            Ok(Ok(exit_status)) => {
+                                let remaining_output = match (out_lines_stream, err_lines_stream) {
+                                    (Right(out_stream), Right(err_stream)) => futures::try_join!(
+                                        read_remaining_lines(out_stream, &resource_limits, &mut out_lines),
+                                        read_remaining_lines(err_stream, &resource_limits, &mut err_lines)
+                                    )
+                                    .map(|_| ()),
+                                    _ => Ok(()),
+                                };
+                                match remaining_output {
+                                    Ok(()) => {
+                                        let status = if output_truncated {
+                                            RunStatus::OutputTruncated
+                                        } else {
+                                            RunStatus::from(exit_status)
+                                        };
+                                        CmdOutput::Finished(CmdResult::new(out_lines, err_lines, status, started_at.elapsed()))
                                     }
+                                    Err(err) => CmdOutput::NotOk(format!("{}", err)),
                                 }
-                            },
+                            }
 
-                            Err(_) => CmdOutput::NotOk("Command timed out".to_string()),
+                            Err(_) => {
+                                if let Some(pid) = running_pid {
+                                    kill_process_group(pid);
+                                }
+                                CmdOutput::Finished(CmdResult::new(out_lines, err_lines, RunStatus::TimedOut, cmd_timeout))
+                            }
                             Ok(Err(err)) => CmdOutput::NotOk(format!("Error running command: {}", err)),
                         };
 
                         cmd_out_send.send(resulting_output).await.ok().unwrap();
 
+                        running_pid = None;
                         handle = Left(futures::future::pending());
                         out_lines_stream = Left(futures::stream::pending());
                         err_lines_stream = Left(futures::stream::pending());
                         out_lines = String::new();
                         err_lines = String::new();
+                        pending_out_lines = String::new();
+                    }
+
+                    _ = partial_flush_interval.tick() => {
+                        if !pending_out_lines.is_empty() {
+                            let chunk = std::mem::take(&mut pending_out_lines);
+                            cmd_out_send.send(CmdOutput::Partial(chunk)).await.ok().unwrap();
+                        }
                     }
                     Some(_) = stop_receive.recv() => break,
                 };
@@ -165,7 +357,13 @@ impl CommandExecutionHandler {
 
     /// execute a single command, returning it's output in this executors cmd_out channel
     pub async fn execute(&mut self, cmd: CommandExecutionRequest) {
-        self.cmd_in_send.send(cmd).await.ok().unwrap();
+        self.cmd_in_send.send(ExecutorMessage::Execute(cmd)).await.ok().unwrap();
+    }
+
+    /// kill the currently running command, including all processes it started.
+    /// The executors cmd_out channel will receive a `RunStatus::Cancelled` result.
+    pub async fn cancel(&mut self) {
+        self.cmd_in_send.send(ExecutorMessage::Cancel).await.ok().unwrap();
     }
 
     /// stop the executor thread
@@ -174,86 +372,210 @@ impl CommandExecutionHandler {
     }
 }
 
+/// lets another thread kill the commands run by `ExecutionMode::run_cmd_blocking`
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    /// whether the commands were cancelled, and the process id of the one that is running
+    state: Arc<Mutex<(bool, Option<u32>)>>,
+}
+
+impl CancelHandle {
+    /// kill the running command, and keep further commands from running
+    pub fn cancel(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = true;
+        if let Some(pid) = state.1.take() {
+            kill_process_group(pid);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().unwrap().0
+    }
+
+    /// remember the running process, killing it right away if the commands were already cancelled
+    fn set_running(&self, pid: Option<u32>) {
+        let mut state = self.state.lock().unwrap();
+        match pid {
+            Some(pid) if state.0 => kill_process_group(pid),
+            pid => state.1 = pid,
+        }
+    }
+}
+
+/// kill all processes in the process group led by the given process
+fn kill_process_group(pid: u32) {
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// append all remaining lines of the given stream to the buffer, respecting the output limit
+async fn read_remaining_lines<R: AsyncBufRead + Unpin>(
+    mut lines: io::Lines<R>,
+    resource_limits: &ResourceLimits,
+    buffer: &mut String,
+) -> io::Result<()> {
+    while let Some(line) = lines.next().await {
+        resource_limits.push_line(buffer, &line?);
+    }
+    Ok(())
+}
+
 impl ExecutionMode {
+    /// build the process that runs the given command using this executionMode,
+    /// returning Err if the command may not be run.
+    /// The process runs in it's own process group, with the given resource limits applied.
+    fn build_command(
+        &self,
+        eval_environment: &[String],
+        resource_limits: &ResourceLimits,
+        cmd: &str,
+    ) -> Result<std::process::Command, String> {
+        let mut command = match self {
+            ExecutionMode::ISOLATED(sandbox) => {
+                let mut command = std::process::Command::new("bwrap");
+                command.args(sandbox.bwrap_args()).args(eval_environment.iter());
+                command
+            }
+
+            ExecutionMode::UNSAFE(policy) => {
+                policy.check(cmd).map_err(|violation| violation.to_string())?;
+                let mut eval_environment = eval_environment.iter();
+                let mut command = std::process::Command::new(eval_environment.next().expect("eval_environment is empty"));
+                command.args(eval_environment);
+                command
+            }
+        };
+        command.arg(cmd);
+
+        // run the command in it's own process group, so it can be killed together with all of it's children.
+        // setpgid and setrlimit are async-signal-safe, so they may be called between fork and exec.
+        let resource_limits = resource_limits.clone();
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                resource_limits.apply()
+            });
+        }
+        Ok(command)
+    }
+
     /// spawn an asynchronously running child using this executionMode, returning Err if something went wrong while spawning.
     /// the command has stdout, stderr and stdin as `Stdio::piped()`, so all are available.
     /// The child is also `kill_on_drop`, so it will be killed when the child value is dropped.
-    fn run_cmd_tokio(&self, eval_environment: &[String], cmd: &str) -> Result<Child, String> {
-        match self {
-            ExecutionMode::ISOLATED => Command::new("bwrap")
-                .args(BUBBLEWRAP_ARGS.split(' '))
-                .args(eval_environment.iter())
-                .arg(cmd)
-                .stdout(Stdio::piped())
-                .stdin(Stdio::piped())
-                .stderr(Stdio::piped())
-                .kill_on_drop(true)
-                .spawn()
-                .map_err(|_| "Unable to spawn command".to_string()),
-
-            ExecutionMode::UNSAFE => {
-                if cmd.contains("rm ") || cmd.contains("mv ") || cmd.contains("dd ") {
-                    return Err("Will not run this command, it's for your own good. Believe me.".to_string());
+    fn run_cmd_tokio(&self, eval_environment: &[String], resource_limits: &ResourceLimits, cmd: &str) -> Result<Child, String> {
+        let command = self.build_command(eval_environment, resource_limits, cmd)?;
+        Command::from(command)
+            .stdout(Stdio::piped())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|_| "Unable to spawn command".to_string())
+    }
+
+    /// blockingly run a command using this executionmode, with its stdout and stderr going straight to pipr's own.
+    /// The given lines are piped into the commands stdin. The output is not limited,
+    /// but the command is killed once it runs longer than the timeout.
+    pub fn run_cmd_passthrough(
+        &self,
+        eval_environment: &[String],
+        resource_limits: &ResourceLimits,
+        cmd: &str,
+        stdin: &[String],
+        timeout: Duration,
+    ) -> Result<RunStatus, String> {
+        let mut child = self
+            .build_command(eval_environment, resource_limits, cmd)?
+            .stdout(Stdio::inherit())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| format!("{}", err))?;
+
+        // write stdin from a separate thread, so a command that doesn't read all of it can't block us
+        if let Some(mut child_stdin) = child.stdin.take() {
+            let stdin_content = stdin.to_vec();
+            std::thread::spawn(move || {
+                use std::io::Write;
+                for line in stdin_content {
+                    let _ = writeln!(child_stdin, "{}", line);
                 }
-                let mut eval_environment = eval_environment.iter();
-                Command::new(eval_environment.next().expect("eval_environment is empty"))
-                    .args(eval_environment)
-                    .arg(cmd)
-                    .stdout(Stdio::piped())
-                    .stdin(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .kill_on_drop(true)
-                    .spawn()
-                    .map_err(|_| "Unable to spawn command".to_string())
+            });
+        }
+
+        let started_at = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait().map_err(|err| format!("{}", err))? {
+                return Ok(RunStatus::from(status));
+            }
+            if started_at.elapsed() > timeout {
+                kill_process_group(child.id());
+                child.wait().map_err(|err| format!("{}", err))?;
+                return Ok(RunStatus::TimedOut);
             }
+            std::thread::sleep(Duration::from_millis(10));
         }
     }
 
     /// blockingly run a command using this executionmode, ignoring it's stderr.
+    /// The given lines are piped into the commands stdin.
     /// return's the stdout if everything went well, or an error message if there was a problem.
-    pub fn run_cmd_blocking(&self, eval_environment: &[String], cmd: &str) -> Result<Vec<String>, String> {
-        // TODO respect stderr, check exit code and clean up
-        match self {
-            ExecutionMode::ISOLATED => std::process::Command::new("bwrap")
-                .args(BUBBLEWRAP_ARGS.split(' '))
-                .args(eval_environment.iter())
-                .arg(cmd)
-                .stdout(Stdio::piped())
-                .stdin(Stdio::null()) // stdin is unused
-                .stderr(Stdio::null()) // stderr is ignored
-                .spawn()
-                .and_then(|mut child| {
-                    let stdout = std::io::BufReader::new(child.stdout.as_mut().unwrap()).lines().collect();
-                    if child.wait()?.success() {
-                        stdout
-                    } else {
-                        Err(std::io::Error::new(std::io::ErrorKind::Other, "Non-zero exit code"))
-                    }
-                })
-                .map_err(|err| format!("{}", err)),
-
-            ExecutionMode::UNSAFE => {
-                if cmd.contains("rm ") || cmd.contains("mv ") || cmd.contains("dd ") {
-                    return Err("Will not run this command, it's for your own good. Believe me.".to_string());
+    pub fn run_cmd_blocking(
+        &self,
+        eval_environment: &[String],
+        resource_limits: &ResourceLimits,
+        cmd: &str,
+        stdin: Option<&[String]>,
+        cancel_handle: Option<&CancelHandle>,
+    ) -> Result<Vec<String>, String> {
+        if cancel_handle.is_some_and(CancelHandle::is_cancelled) {
+            return Err("Cancelled".to_string());
+        }
+        // TODO respect stderr
+        self.build_command(eval_environment, resource_limits, cmd)?
+            .stdout(Stdio::piped())
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stderr(Stdio::null()) // stderr is ignored
+            .spawn()
+            .and_then(|mut child| {
+                if let Some(cancel_handle) = cancel_handle {
+                    cancel_handle.set_running(Some(child.id()));
                 }
-                let mut eval_environment = eval_environment.iter();
-                std::process::Command::new(eval_environment.next().expect("eval_environment is empty"))
-                    .args(eval_environment)
-                    .arg(cmd)
-                    .stdout(Stdio::piped())
-                    .stdin(Stdio::null()) // stdin is unused
-                    .stderr(Stdio::null()) // stderr is ignored
-                    .spawn()
-                    .and_then(|mut child| {
-                        let stdout = std::io::BufReader::new(child.stdout.as_mut().unwrap()).lines().collect();
-                        if child.wait()?.success() {
-                            stdout
-                        } else {
-                            Err(std::io::Error::new(std::io::ErrorKind::Other, "Non-zero exit code"))
+                // write stdin from a separate thread, so a command that doesn't read all of it can't block us
+                if let (Some(mut child_stdin), Some(stdin_content)) = (child.stdin.take(), stdin) {
+                    let stdin_content = stdin_content.to_vec();
+                    std::thread::spawn(move || {
+                        use std::io::Write;
+                        for line in stdin_content {
+                            let _ = writeln!(child_stdin, "{}", line);
                         }
-                    })
-                    .map_err(|err| format!("{}", err))
-            }
-        }
+                    });
+                }
+                let mut stdout = String::new();
+                for line in std::io::BufReader::new(child.stdout.as_mut().unwrap()).lines() {
+                    if !resource_limits.push_line(&mut stdout, &line?) {
+                        kill_process_group(child.id());
+                        child.wait()?;
+                        return Ok(stdout.lines().map(|line| line.to_string()).collect());
+                    }
+                }
+                let status = child.wait()?;
+                if let Some(cancel_handle) = cancel_handle {
+                    cancel_handle.set_running(None);
+                    if cancel_handle.is_cancelled() {
+                        return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+                    }
+                }
+                if status.success() {
+                    Ok(stdout.lines().map(|line| line.to_string()).collect())
+                } else {
+                    Err(std::io::Error::new(std::io::ErrorKind::Other, "Non-zero exit code"))
+                }
+            })
+            .map_err(|err| format!("{}", err))
     }
 }
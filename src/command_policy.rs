@@ -0,0 +1,439 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::shell_lexer::{self, Token, TokenKind};
+
+/// words that start a compound command or modify the following command, and thus aren't commands themselves
+const KEYWORDS: &[&str] = &[
+    "!", "{", "}", "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "time", "function",
+];
+
+/// keywords whose following words, up to the next operator, aren't commands
+const LIST_KEYWORDS: &[&str] = &["for", "select", "case", "in", "esac"];
+
+/// a wrapper command, with the letters of its short options and the long options that take a value
+type Wrapper = (&'static str, &'static str, &'static [&'static str]);
+
+/// commands that run the command given in their arguments
+const WRAPPER_COMMANDS: &[Wrapper] = &[
+    ("xargs", "adEILnPs", &["--arg-file", "--delimiter", "--max-args", "--max-procs", "--max-chars", "--process-slot-var"]),
+    ("sudo", "CDgpRrTtUu", &["--close-from", "--chdir", "--group", "--prompt", "--chroot", "--role", "--type", "--user"]),
+    ("doas", "Cu", &[]),
+    ("env", "uCS", &["--unset", "--chdir", "--split-string"]),
+    ("nice", "n", &["--adjustment"]),
+    ("nohup", "", &[]),
+    ("timeout", "ks", &["--kill-after", "--signal"]),
+    ("stdbuf", "ioe", &["--input", "--output", "--error"]),
+    ("command", "", &[]),
+    ("exec", "a", &[]),
+    ("builtin", "", &[]),
+    ("ionice", "cn", &["--class", "--classdata"]),
+];
+
+/// shells whose `-c` argument is a command that is checked as well
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+/// options of the shells that take a value
+const SHELL_OPTIONS_WITH_VALUE: &[&str] = &["-o", "+o", "-O", "+O", "--rcfile", "--init-file"];
+
+/// Rules deciding which commands may be run with --no-isolation,
+/// read from the `[command_policy]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandPolicy {
+    /// if not empty, only these commands may be run
+    pub allow_commands: Vec<String>,
+    /// commands that may never be run
+    pub deny_commands: Vec<String>,
+    /// arguments that may not be passed to specific commands, like `find = ["-delete"]`
+    pub deny_arguments: BTreeMap<String, Vec<String>>,
+    /// allow redirecting output into arbitrary files
+    pub allow_output_redirection: bool,
+    /// files output may always be redirected to
+    pub allowed_redirection_targets: Vec<String>,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        let to_strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        CommandPolicy {
+            allow_commands: Vec::new(),
+            deny_commands: to_strings(&[
+                "rm", "rmdir", "unlink", "mv", "cp", "dd", "shred", "truncate", "mkfs", "chmod", "chown", "chgrp", "ln",
+                "tee", "kill", "pkill", "killall", "sudo", "doas", "su", "reboot", "shutdown",
+            ]),
+            deny_arguments: vec![
+                ("find", to_strings(&["-delete", "-fprint", "-fprint0", "-fprintf", "-fls"])),
+                ("sed", to_strings(&["-i", "--in-place"])),
+                ("perl", to_strings(&["-i"])),
+                ("sort", to_strings(&["-o", "--output"])),
+                ("git", to_strings(&["push", "reset", "clean", "checkout", "commit"])),
+            ]
+            .into_iter()
+            .map(|(command, arguments)| (command.to_string(), arguments))
+            .collect(),
+            allow_output_redirection: false,
+            allowed_redirection_targets: to_strings(&["/dev/null", "/dev/stdout", "/dev/stderr"]),
+        }
+    }
+}
+
+/// The reason a command was refused
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PolicyViolation {
+    /// the token that violated the policy
+    pub token: String,
+    /// byte offset of the token in the checked command
+    pub offset: usize,
+    pub reason: String,
+}
+
+impl Display for PolicyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Will not run this command: `{}` at position {} {}.\nThis is configured in the [command_policy] section of your config.",
+            self.token,
+            self.offset + 1,
+            self.reason
+        )
+    }
+}
+
+/// a command with its arguments, unquoted, together with the offsets of the words
+struct SimpleCommand {
+    words: Vec<(String, usize)>,
+}
+
+impl CommandPolicy {
+    /// check the given shell code, returning the first violation of this policy
+    pub fn check(&self, cmd: &str) -> Result<(), PolicyViolation> {
+        let tokens = shell_lexer::tokenize(cmd);
+        let mut simple_command = SimpleCommand { words: Vec::new() };
+        let mut skip_until_operator = false;
+        let mut tokens_iter = tokens.iter().peekable();
+
+        while let Some(token) = tokens_iter.next() {
+            let text = &cmd[token.span.clone()];
+            match token.kind {
+                TokenKind::Word => {
+                    self.check_substitutions(text, token.span.start)?;
+                    let value = shell_lexer::unquote(text);
+                    let is_command_position = simple_command.words.is_empty();
+                    let is_prefix = is_command_position && (KEYWORDS.contains(&text) || is_assignment(text));
+                    if skip_until_operator || is_prefix {
+                        continue;
+                    } else if is_command_position && LIST_KEYWORDS.contains(&text) {
+                        skip_until_operator = true;
+                    } else {
+                        simple_command.words.push((value, token.span.start));
+                    }
+                }
+                TokenKind::Redirect => {
+                    let target = tokens_iter.next_if(|token| token.kind == TokenKind::Word);
+                    self.check_redirection(cmd, token, target)?;
+                }
+                TokenKind::Heredoc => {
+                    tokens_iter.next_if(|token| token.kind == TokenKind::Word);
+                }
+                TokenKind::Comment | TokenKind::HeredocBody => {}
+                _ => {
+                    self.check_simple_command(&simple_command.words)?;
+                    simple_command.words.clear();
+                    skip_until_operator = false;
+                }
+            }
+        }
+        self.check_simple_command(&simple_command.words)
+    }
+
+    /// check the commands within command- and process-substitutions of a word
+    fn check_substitutions(&self, word: &str, word_offset: usize) -> Result<(), PolicyViolation> {
+        for span in shell_lexer::command_substitutions(word) {
+            let inner_offset = word_offset + span.start;
+            self.check(&word[span]).map_err(|violation| PolicyViolation {
+                offset: inner_offset + violation.offset,
+                ..violation
+            })?;
+        }
+        Ok(())
+    }
+
+    fn check_redirection(&self, cmd: &str, operator: &Token, target: Option<&Token>) -> Result<(), PolicyViolation> {
+        let operator_text = &cmd[operator.span.clone()];
+        let writes_output = operator_text.contains('>');
+        if !writes_output || self.allow_output_redirection {
+            return Ok(());
+        }
+        let target_text = target.map(|target| shell_lexer::unquote(&cmd[target.span.clone()])).unwrap_or_default();
+        let duplicates_descriptor = operator_text.ends_with(">&") && target_text.chars().all(|c| c.is_ascii_digit() || c == '-');
+        if duplicates_descriptor || self.allowed_redirection_targets.contains(&target_text) {
+            return Ok(());
+        }
+        Err(PolicyViolation {
+            token: format!("{}{}", operator_text, target_text),
+            offset: operator.span.start,
+            reason: "redirects output into a file".to_string(),
+        })
+    }
+
+    fn check_simple_command(&self, words: &[(String, usize)]) -> Result<(), PolicyViolation> {
+        let ((command, offset), arguments) = match words.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let name = command.rsplit('/').next().unwrap_or(command);
+        let violation = |token: &str, offset: usize, reason: String| {
+            Err(PolicyViolation {
+                token: token.to_string(),
+                offset,
+                reason,
+            })
+        };
+
+        if !self.allow_commands.is_empty() && !self.allow_commands.iter().any(|allowed| allowed == name) {
+            return violation(command, *offset, "is not in allow_commands".to_string());
+        }
+        if self.deny_commands.iter().any(|denied| denied == name) {
+            return violation(command, *offset, "is in deny_commands".to_string());
+        }
+        if let Some(denied_arguments) = self.deny_arguments.get(name) {
+            for (argument, offset) in arguments {
+                if let Some(denied) = denied_arguments.iter().find(|denied| argument_matches(argument, denied)) {
+                    return violation(argument, *offset, format!("matches `{}`, which is denied for {}", denied, name));
+                }
+            }
+        }
+
+        if let Some(wrapper) = WRAPPER_COMMANDS.iter().find(|(wrapper, _, _)| *wrapper == name) {
+            let wrapped = skip_wrapper_options(wrapper, arguments);
+            if let Some((script, script_offset)) = wrapped.split_string {
+                self.check(&script).map_err(|violation| PolicyViolation {
+                    offset: script_offset,
+                    ..violation
+                })?;
+            }
+            self.check_simple_command(wrapped.words)?;
+        } else if name == "find" {
+            // check the commands run by -exec, -execdir, -ok and -okdir
+            let mut arguments = arguments.iter();
+            while let Some((argument, _)) = arguments.next() {
+                if argument.starts_with("-exec") || argument.starts_with("-ok") {
+                    let executed = arguments
+                        .by_ref()
+                        .take_while(|(argument, _)| argument != ";" && argument != "+")
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    self.check_simple_command(&executed)?;
+                }
+            }
+        } else if SHELLS.contains(&name) || name == "eval" {
+            let script = if name == "eval" {
+                arguments.first()
+            } else {
+                shell_script(arguments)
+            };
+            if let Some((script, script_offset)) = script {
+                self.check(script).map_err(|violation| PolicyViolation {
+                    offset: *script_offset,
+                    ..violation
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the command run by a wrapper command
+struct WrappedCommand<'a> {
+    /// the wrapped command and its arguments
+    words: &'a [(String, usize)],
+    /// the value of `env -S`, which is split into a command as well
+    split_string: Option<(String, usize)>,
+}
+
+/// skip the options of a wrapper command (like `xargs -n 1`), returning the command it runs
+fn skip_wrapper_options<'a>(
+    (wrapper, short_with_value, long_with_value): &Wrapper,
+    arguments: &'a [(String, usize)],
+) -> WrappedCommand<'a> {
+    let mut idx = 0;
+    let mut split_string = None;
+    while let Some((argument, offset)) = arguments.get(idx) {
+        idx += 1;
+        // the option that takes a value, and the value if it's part of the same argument
+        let option_with_value = if argument == "--" {
+            break;
+        } else if argument.starts_with("--") {
+            match argument.split_once('=') {
+                Some((option, value)) => Some((option.to_string(), Some(value))),
+                None => long_with_value.contains(&argument.as_str()).then(|| (argument.clone(), None)),
+            }
+        } else if argument.starts_with('-') {
+            // in a group of short options like `-0n1`, the first one that takes a value gets the rest of the group
+            argument.char_indices().skip(1).find(|(_, c)| short_with_value.contains(*c)).map(|(pos, c)| {
+                let value = &argument[pos + c.len_utf8()..];
+                (format!("-{}", c), Some(value).filter(|value| !value.is_empty()))
+            })
+        } else if (*wrapper == "env" && is_assignment(argument))
+            || (*wrapper == "timeout" && argument.starts_with(|c: char| c.is_ascii_digit()))
+        {
+            // a variable for env, or the duration of timeout
+            None
+        } else {
+            idx -= 1;
+            break;
+        };
+
+        if let Some((option, attached_value)) = option_with_value {
+            let value = match attached_value {
+                Some(value) => Some((value.to_string(), *offset)),
+                None => {
+                    idx += 1;
+                    arguments.get(idx - 1).cloned()
+                }
+            };
+            if *wrapper == "env" && (option == "-S" || option == "--split-string") {
+                split_string = value;
+            }
+        }
+    }
+    WrappedCommand {
+        words: arguments.get(idx..).unwrap_or(&[]),
+        split_string,
+    }
+}
+
+/// the script given to a shell with `-c`, or with a group of short options containing `c` like `-ec`.
+/// It's the first argument after the options.
+fn shell_script(arguments: &[(String, usize)]) -> Option<&(String, usize)> {
+    let mut arguments = arguments.iter();
+    let mut has_script_option = false;
+    while let Some(argument) = arguments.next() {
+        let option = argument.0.as_str();
+        if option == "--" {
+            break;
+        } else if !option.starts_with('-') && !option.starts_with('+') {
+            return Some(argument).filter(|_| has_script_option);
+        } else if option == "--command" || (!option.starts_with("--") && option[1..].contains('c')) {
+            has_script_option = true;
+        } else if SHELL_OPTIONS_WITH_VALUE.contains(&option) {
+            arguments.next();
+        }
+    }
+    arguments.next().filter(|_| has_script_option)
+}
+
+/// whether the given argument matches a denied argument.
+/// Single-letter options also match when combined with other options, like `-ni` matches `-i`.
+fn argument_matches(argument: &str, denied: &str) -> bool {
+    let is_short_option = denied.len() == 2 && denied.starts_with('-') && denied != "--";
+    if is_short_option {
+        let denied_letter = &denied[1..];
+        argument.starts_with('-') && !argument.starts_with("--") && argument[1..].contains(denied_letter)
+    } else {
+        argument == denied || argument.starts_with(&format!("{}=", denied))
+    }
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(idx) => idx > 0 && word[..idx].chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn refused_token(cmd: &str) -> Option<String> {
+        CommandPolicy::default().check(cmd).err().map(|violation| violation.token)
+    }
+
+    #[test]
+    fn test_allowed_commands() {
+        assert_eq!(refused_token("grep \"form \" file | wc -l"), None);
+        assert_eq!(refused_token("echo 'rm -rf /'"), None);
+        assert_eq!(refused_token("sed -n 's/a/b/p' 2>&1 >/dev/null"), None);
+        assert_eq!(refused_token("find . -name '*.rs' -exec wc -l {} +"), None);
+        assert_eq!(refused_token("for f in rm mv; do echo $f; done"), None);
+        assert_eq!(refused_token("echo $((1 + 2)) # rm"), None);
+        assert_eq!(refused_token("xargs -n 1 echo"), None);
+    }
+
+    #[test]
+    fn test_denied_commands() {
+        assert_eq!(refused_token("ls | xargs rm"), Some("rm".into()));
+        assert_eq!(refused_token("ls | xargs -I {} rm {}"), Some("rm".into()));
+        assert_eq!(refused_token("\"rm\" -rf foo"), Some("rm".into()));
+        assert_eq!(refused_token("/bin/rm foo"), Some("/bin/rm".into()));
+        assert_eq!(refused_token("FOO=bar rm foo"), Some("rm".into()));
+        assert_eq!(refused_token("echo $(rm foo)"), Some("rm".into()));
+        assert_eq!(refused_token("echo \"`mv a b`\""), Some("mv".into()));
+        assert_eq!(refused_token("bash -c 'cat a; rm a'"), Some("rm".into()));
+        assert_eq!(refused_token("(cd /tmp && rm foo)"), Some("rm".into()));
+        assert_eq!(refused_token("env -u X timeout 5 rm foo"), Some("rm".into()));
+        assert_eq!(refused_token("find . -exec rm {} \\;"), Some("rm".into()));
+    }
+
+    #[test]
+    fn test_wrapper_options() {
+        // options that are flags for one wrapper don't swallow the wrapped command
+        assert_eq!(refused_token("env -i rm -rf foo"), Some("rm".into()));
+        assert_eq!(refused_token("xargs -p rm"), Some("rm".into()));
+        assert_eq!(refused_token("command -p rm x"), Some("rm".into()));
+        assert_eq!(refused_token("xargs -0n1 rm"), Some("rm".into()));
+        assert_eq!(refused_token("xargs -0n 1 rm"), Some("rm".into()));
+        assert_eq!(refused_token("timeout --signal=KILL -k 1 5 rm x"), Some("rm".into()));
+        assert_eq!(refused_token("env -S 'rm -rf foo'"), Some("rm".into()));
+        assert_eq!(refused_token("nice -n 5 --adjustment 3 cat"), None);
+    }
+
+    #[test]
+    fn test_shell_scripts() {
+        assert_eq!(refused_token("sh -ec 'rm x'"), Some("rm".into()));
+        assert_eq!(refused_token("bash -xc 'rm x'"), Some("rm".into()));
+        assert_eq!(refused_token("bash -o pipefail -c 'cat a | rm a'"), Some("rm".into()));
+        assert_eq!(refused_token("bash -c -e 'rm x'"), Some("rm".into()));
+        assert_eq!(refused_token("sh -e 'rm x'"), None);
+    }
+
+    #[test]
+    fn test_denied_arguments() {
+        assert_eq!(refused_token("find . -delete"), Some("-delete".into()));
+        assert_eq!(refused_token("sed -ni 's/a/b/' file"), Some("-ni".into()));
+        assert_eq!(refused_token("sed --in-place=.bak 's/a/b/' file"), Some("--in-place=.bak".into()));
+        assert_eq!(refused_token("sort -u file"), None);
+    }
+
+    #[test]
+    fn test_redirections() {
+        assert_eq!(refused_token("echo foo > file"), Some(">file".into()));
+        assert_eq!(refused_token("echo foo >> file"), Some(">>file".into()));
+        assert_eq!(refused_token("echo foo &> file"), Some("&>file".into()));
+        assert_eq!(refused_token("cat < file"), None);
+        assert_eq!(refused_token("cat 2>/dev/null"), None);
+
+        let policy = CommandPolicy {
+            allow_output_redirection: true,
+            ..CommandPolicy::default()
+        };
+        assert_eq!(policy.check("echo foo > file"), Ok(()));
+    }
+
+    #[test]
+    fn test_allow_list_and_offsets() {
+        let policy = CommandPolicy {
+            allow_commands: vec!["cat".into(), "grep".into()],
+            ..CommandPolicy::default()
+        };
+        assert_eq!(policy.check("cat foo | grep bar"), Ok(()));
+        let violation = policy.check("cat foo | awk 1").unwrap_err();
+        assert_eq!((violation.token.as_str(), violation.offset), ("awk", 10));
+
+        let violation = CommandPolicy::default().check("echo $(rm x)").unwrap_err();
+        assert_eq!(violation.offset, 7);
+    }
+}
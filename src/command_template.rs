@@ -1,6 +1,11 @@
+use std::collections::HashMap;
+use std::ops::Range;
 use std::process::Command;
 pub const COMMAND_TEMPLATE_PLACEHOLDER_TOKEN: &str = "??";
 
+const VARIABLE_START: &str = "{{?";
+const VARIABLE_END: &str = "}}";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct CommandTemplate(String);
 
@@ -25,3 +30,114 @@ impl CommandTemplate {
         command
     }
 }
+
+/// a variable in a bookmarked command, written as `{{?name}}`, or `{{?name:default}}` to give it a default value.
+/// `{{?` isn't valid in go (and thus helm), jinja or handlebars templates, so braces of those are never read as variables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateVariable {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// the variables of a command, in the order they first occur in.
+/// Variables that occur multiple times are listed once, with the first default given for them.
+pub fn template_variables(text: &str) -> Vec<TemplateVariable> {
+    let mut variables: Vec<TemplateVariable> = Vec::new();
+    for (_, variable) in variable_occurrences(text) {
+        match variables.iter_mut().find(|existing| existing.name == variable.name) {
+            Some(existing) => {
+                if existing.default.is_none() {
+                    existing.default = variable.default;
+                }
+            }
+            None => variables.push(variable),
+        }
+    }
+    variables
+}
+
+/// replace every variable by its value. Variables without a value are replaced by their default, if they have one.
+/// Values are inserted as they are, without any shell quoting, so they can be several arguments or options.
+pub fn substitute_variables(text: &str, values: &HashMap<String, String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last_end = 0;
+    for (span, variable) in variable_occurrences(text) {
+        let value = values.get(&variable.name).or(variable.default.as_ref());
+        if let Some(value) = value {
+            result.push_str(&text[last_end..span.start]);
+            result.push_str(value);
+            last_end = span.end;
+        }
+    }
+    result.push_str(&text[last_end..]);
+    result
+}
+
+/// byte ranges of all variables in the text, with the parsed variable.
+/// Braces that don't contain a valid variable name, like in `{{?a b}}`, are ignored.
+fn variable_occurrences(text: &str) -> Vec<(Range<usize>, TemplateVariable)> {
+    let mut occurrences = Vec::new();
+    let mut search_start = 0;
+    while let Some(start) = text[search_start..].find(VARIABLE_START).map(|idx| idx + search_start) {
+        let content_start = start + VARIABLE_START.len();
+        let end = match text[content_start..].find(VARIABLE_END) {
+            Some(idx) => content_start + idx + VARIABLE_END.len(),
+            None => break,
+        };
+        let content = &text[content_start..end - VARIABLE_END.len()];
+        let (name, default) = match content.split_once(':') {
+            Some((name, default)) => (name, Some(default.to_owned())),
+            None => (content, None),
+        };
+        let is_valid_name = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+        if is_valid_name && !content.contains('\n') {
+            let name = name.to_owned();
+            occurrences.push((start..end, TemplateVariable { name, default }));
+            search_start = end;
+        } else {
+            search_start = start + 1;
+        }
+    }
+    occurrences
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashmap;
+
+    #[test]
+    fn test_template_variables() {
+        let variables = template_variables("grep {{?pattern}} {{?file:/tmp/log}} | head -n {{?n:10}} | grep {{?pattern:x}}");
+        let names = variables.iter().map(|variable| variable.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["pattern", "file", "n"]);
+        assert_eq!(variables[0].default, Some("x".to_string()));
+        assert_eq!(variables[1].default, Some("/tmp/log".to_string()));
+
+        assert_eq!(template_variables("awk '{{print $1}}' | sed 's/{{?}}//'"), Vec::new());
+        assert_eq!(template_variables("echo {{?a\nb}} {{?a b}} {{?unclosed"), Vec::new());
+    }
+
+    #[test]
+    fn test_substitute_variables() {
+        let values = hashmap! { "pattern".to_string() => "error".to_string() };
+        assert_eq!(
+            substitute_variables("grep {{?pattern}} {{?file:log.txt}} {{pattern}} {{?missing}}", &values),
+            "grep error log.txt {{pattern}} {{?missing}}"
+        );
+        assert_eq!(substitute_variables("awk '{{print}}'", &values), "awk '{{print}}'");
+    }
+
+    #[test]
+    fn test_templates_are_not_variables() {
+        let go_template = "kubectl get pods -o go-template='{{range $i, $p := .items}}{{$i}} {{$p.metadata.name}}{{end}}'";
+        let command = format!("{} | grep {{{{?pattern}}}}", go_template);
+        let names = template_variables(&command).into_iter().map(|variable| variable.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["pattern"]);
+        let values = hashmap! { "pattern".to_string() => "web".to_string() };
+        assert_eq!(substitute_variables(&command, &values), format!("{} | grep web", go_template));
+
+        let jinja = "j2 <(echo '{{ name | upper }}{% if x %}{{x}}{% endif %}') | handlebars '{{#each a}}{{@index}}{{/each}}'";
+        assert_eq!(template_variables(jinja), Vec::new());
+    }
+}
@@ -1,37 +1,202 @@
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use chrono::{DateTime, Local};
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::bookmarks::{self, BookmarkInfo};
+use crate::command_evaluation::{RunStatus, RunSummary};
 
 const SERIALIZATION_ENTRY_SEPERATOR: &str = "---";
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct CommandEntry(Vec<String>);
+/// the first line of files in the current format. Files without it are read as the original format,
+/// which only contained the lines of the entries, separated by `---`.
+const SERIALIZATION_HEADER_V2: &str = "#pipr-commandlist v2";
+
+/// in the current format, lines starting with this are metadata of the entry that follows
+const SERIALIZATION_METADATA_PREFIX: char = '@';
+
+/// in the current format, lines starting with this are taken literally, without the escape character.
+/// This is used for lines that would otherwise be read as metadata, separators or escaped lines, and for empty lines.
+const SERIALIZATION_ESCAPE: char = '\\';
+
+#[derive(Debug, Clone)]
+pub struct CommandEntry {
+    lines: Vec<String>,
+    /// summary of the latest evaluation of this command in the current session
+    pub last_run: Option<RunSummary>,
+    pub metadata: EntryMetadata,
+    /// the name, description and tags, if this entry is a bookmark
+    pub bookmark: BookmarkInfo,
+}
+
+/// information about an entry that is stored in the history file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// when the command was last run
+    pub timestamp: Option<DateTime<Local>>,
+    /// the directory the command was last run in
+    pub cwd: Option<String>,
+    pub exit_status: Option<RunStatus>,
+    /// how long the last run took
+    pub duration: Option<Duration>,
+    /// how often the command was run
+    pub run_count: u32,
+}
+
+impl EntryMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == EntryMetadata::default()
+    }
+
+    /// record a run of the command that just finished
+    pub fn record_run(&mut self, summary: &RunSummary) {
+        self.timestamp = Some(Local::now());
+        self.cwd = std::env::current_dir().ok().map(|cwd| cwd.to_string_lossy().to_string());
+        self.exit_status = Some(summary.status);
+        self.duration = Some(summary.duration);
+        self.run_count += 1;
+    }
+
+    fn serialize(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(timestamp) = self.timestamp {
+            lines.push(format!("time {}", timestamp.to_rfc3339()));
+        }
+        if let Some(cwd) = &self.cwd {
+            lines.push(format!("cwd {}", escape_value(cwd)));
+        }
+        if let Some(exit_status) = self.exit_status {
+            lines.push(format!("status {}", exit_status));
+        }
+        if let Some(duration) = self.duration {
+            lines.push(format!("duration_ms {}", duration.as_millis()));
+        }
+        if self.run_count > 0 {
+            lines.push(format!("runs {}", self.run_count));
+        }
+        lines.into_iter().map(|line| format!("{}{}", SERIALIZATION_METADATA_PREFIX, line)).collect()
+    }
+
+    /// read a metadata line, without its prefix. Unknown or invalid metadata is ignored,
+    /// so files written by newer versions can still be read.
+    fn deserialize_line(&mut self, line: &str) {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "time" => self.timestamp = DateTime::parse_from_rfc3339(value).ok().map(|time| time.with_timezone(&Local)),
+            "cwd" => self.cwd = Some(unescape_value(value)),
+            "status" => self.exit_status = value.parse().ok(),
+            "duration_ms" => self.duration = value.parse().ok().map(Duration::from_millis),
+            "runs" => self.run_count = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+}
+
+impl Display for EntryMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(timestamp) = self.timestamp {
+            parts.push(timestamp.format("%Y-%m-%d %H:%M").to_string());
+        }
+        if let Some(cwd) = &self.cwd {
+            parts.push(cwd.clone());
+        }
+        if let Some(exit_status) = self.exit_status {
+            parts.push(exit_status.to_string());
+        }
+        if let Some(duration) = self.duration {
+            parts.push(format!("{}ms", duration.as_millis()));
+        }
+        if self.run_count > 0 {
+            parts.push(format!("{} runs", self.run_count));
+        }
+        write!(f, "{}", parts.join(" | "))
+    }
+}
+
+/// metadata values are written on a single line, so newlines and backslashes are escaped
+fn escape_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_value(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                result.push('\\');
+                chars.next();
+            }
+            (c, _) => result.push(c),
+        }
+    }
+    result
+}
+
+/// entries are identified by their content, regardless of their metadata
+impl PartialEq for CommandEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.lines == other.lines
+    }
+}
+impl Eq for CommandEntry {}
 
 impl CommandEntry {
     pub fn new(content: Vec<String>) -> CommandEntry {
-        CommandEntry(content)
+        CommandEntry {
+            lines: content,
+            last_run: None,
+            metadata: EntryMetadata::default(),
+            bookmark: BookmarkInfo::default(),
+        }
     }
     pub fn lines(&self) -> &Vec<String> {
-        &self.0
+        &self.lines
     }
     pub fn as_string(&self) -> String {
         self.lines().join("\n")
     }
 }
 
+/// how a list is stored in its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// the lines of the entries, separated by `---`, with metadata lines in front of them
+    Lines,
+    /// bookmarks with their names, descriptions and tags, as toml
+    BookmarksToml,
+}
+
 #[derive(Debug, Clone)]
 pub struct CommandList {
     entries: Vec<CommandEntry>,
+    format: FileFormat,
+    /// the entries as they were last read from or written to the file.
+    /// Entries in the file that are not in here were added by other instances of pipr.
+    synced_entries: Vec<CommandEntry>,
     file: Option<PathBuf>,
     max_size: Option<usize>,
+    /// the error of the last failed attempt to read or write the file
+    pub io_error: Option<String>,
 }
 
 impl CommandList {
     pub fn new(file: Option<PathBuf>, max_size: Option<usize>) -> CommandList {
         CommandList {
             entries: Vec::new(),
+            format: FileFormat::Lines,
+            synced_entries: Vec::new(),
             max_size,
             file,
+            io_error: None,
         }
     }
 
@@ -44,8 +209,15 @@ impl CommandList {
         self.write_to_file();
     }
 
-    pub fn push(&mut self, command: CommandEntry) {
+    /// add an entry, unless it's empty or the same as the last one.
+    /// If the command is already in the list, the new entry continues its metadata, like the run count.
+    pub fn push(&mut self, mut command: CommandEntry) {
         if !command.as_string().is_empty() && self.entries.last() != Some(&command) {
+            if command.metadata.is_empty() {
+                if let Some(previous) = self.entries.iter().rev().find(|entry| **entry == command) {
+                    command.metadata = previous.metadata.clone();
+                }
+            }
             self.entries.push(command);
             if let Some(max_size) = self.max_size {
                 if self.len() > max_size {
@@ -55,9 +227,39 @@ impl CommandList {
             self.write_to_file();
         }
     }
-    pub fn as_strings(&self) -> Vec<String> {
-        self.entries.iter().map(|bookmark| bookmark.as_string()).collect()
+
+    /// store the summary of a run in the most recent entry matching the given command
+    pub fn record_run(&mut self, command: &CommandEntry, summary: RunSummary) {
+        if let Some(entry) = self.entries.iter_mut().rev().find(|entry| *entry == command) {
+            entry.last_run = Some(summary);
+            entry.metadata.record_run(&summary);
+            self.write_to_file();
+        }
     }
+
+    /// merge entries imported from elsewhere, oldest first, into the list.
+    /// They are added before the existing entries, skipping ones that are already in the list;
+    /// of repeated entries, only the most recent one is kept. Returns how many entries were added.
+    pub fn import(&mut self, imported: Vec<CommandEntry>) -> usize {
+        let mut new_entries: Vec<CommandEntry> = Vec::new();
+        for entry in imported.into_iter().rev() {
+            if !entry.as_string().is_empty() && !self.entries.contains(&entry) && !new_entries.contains(&entry) {
+                new_entries.push(entry);
+            }
+        }
+        new_entries.reverse();
+
+        let available = self.max_size.map(|max_size| max_size.saturating_sub(self.len()));
+        if let Some(available) = available {
+            new_entries.drain(0..new_entries.len().saturating_sub(available));
+        }
+        let added = new_entries.len();
+        new_entries.append(&mut self.entries);
+        self.entries = new_entries;
+        self.write_to_file();
+        added
+    }
+
     pub fn get_at(&self, idx: usize) -> Option<&CommandEntry> {
         self.entries.get(idx)
     }
@@ -84,23 +286,57 @@ impl CommandList {
     }
 
     pub fn serialize(&self) -> String {
-        self.as_strings().join(&format!("\n{}\n", SERIALIZATION_ENTRY_SEPERATOR))
+        match self.format {
+            FileFormat::Lines => self.serialize_lines(),
+            FileFormat::BookmarksToml => bookmarks::serialize_bookmarks(&self.entries),
+        }
+    }
+
+    fn serialize_lines(&self) -> String {
+        let entries = self.entries.iter().map(|entry| {
+            let escaped_lines = entry.lines().iter().map(|line| {
+                let needs_escape = line.is_empty()
+                    || line == SERIALIZATION_ENTRY_SEPERATOR
+                    || line.starts_with(SERIALIZATION_METADATA_PREFIX)
+                    || line.starts_with(SERIALIZATION_ESCAPE);
+                if needs_escape {
+                    format!("{}{}", SERIALIZATION_ESCAPE, line)
+                } else {
+                    line.clone()
+                }
+            });
+            entry.metadata.serialize().into_iter().chain(escaped_lines).collect::<Vec<_>>().join("\n")
+        });
+        let entries = entries.collect::<Vec<_>>().join(&format!("\n{}\n", SERIALIZATION_ENTRY_SEPERATOR));
+        format!("{}\n{}", SERIALIZATION_HEADER_V2, entries)
     }
 
     pub fn deserialize(path: Option<PathBuf>, max_size: Option<usize>, lines: &str) -> CommandList {
         let mut entries = CommandList::new(path, max_size);
-        let mut current_entry = Vec::new();
-        for line in lines.lines().filter(|x| !x.is_empty()) {
+        let mut lines = lines.lines().peekable();
+        let is_v2 = lines.peek() == Some(&SERIALIZATION_HEADER_V2);
+        if is_v2 {
+            lines.next();
+        }
+
+        let mut current_entry = CommandEntry::new(Vec::new());
+        for line in lines {
             if line == SERIALIZATION_ENTRY_SEPERATOR {
-                entries.push(CommandEntry::new(current_entry));
-                current_entry = Vec::new();
+                entries.push_deserialized(current_entry);
+                current_entry = CommandEntry::new(Vec::new());
+            } else if !is_v2 {
+                if !line.is_empty() {
+                    current_entry.lines.push(line.to_owned());
+                }
+            } else if let Some(escaped_line) = line.strip_prefix(SERIALIZATION_ESCAPE) {
+                current_entry.lines.push(escaped_line.to_owned());
+            } else if let Some(metadata) = line.strip_prefix(SERIALIZATION_METADATA_PREFIX) {
+                current_entry.metadata.deserialize_line(metadata);
             } else {
-                current_entry.push(line.to_owned());
+                current_entry.lines.push(line.to_owned());
             }
         }
-        if !current_entry.is_empty() {
-            entries.push(CommandEntry::new(current_entry)); // add last started bookmark
-        }
+        entries.push_deserialized(current_entry); // add last started entry
 
         // remove entries to fit into max_size
         if let Some(max_size) = max_size {
@@ -111,20 +347,306 @@ impl CommandList {
         entries
     }
 
-    pub fn write_to_file(&self) {
-        if let Some(file) = &self.file {
-            let mut file = File::create(file).unwrap();
-            file.write_all(self.serialize().as_bytes()).unwrap();
+    /// add a read entry, skipping empty ones and repetitions, without writing the file
+    fn push_deserialized(&mut self, entry: CommandEntry) {
+        if !entry.lines.is_empty() && self.entries.last() != Some(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    /// save the entries to the file, if there is one. Failures are stored in `io_error`.
+    pub fn write_to_file(&mut self) {
+        if let Some(path) = self.file.clone() {
+            self.io_error = self
+                .sync_with_file(&path)
+                .err()
+                .map(|err| format!("could not save {}: {}", path.display(), err));
+        }
+    }
+
+    /// merge the entries that other instances of pipr added to the file since it was last synced,
+    /// then replace the file. The file is locked meanwhile, so concurrent writes don't get lost.
+    fn sync_with_file(&mut self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _lock = FileLock::acquire(&path.with_file_name(format!("{}.lock", file_name(path))))?;
+        let on_disk = match fs::read_to_string(path) {
+            Ok(contents) => parse_file(self.format, &contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        self.merge_foreign_entries(on_disk);
+        write_atomically(path, &self.serialize())?;
+        self.synced_entries = self.entries.clone();
+        Ok(())
+    }
+
+    /// add the entries of the file that are neither known from the last sync nor in the list.
+    /// They go before the entries that were added to the list since the last sync.
+    fn merge_foreign_entries(&mut self, on_disk: Vec<CommandEntry>) {
+        let foreign_entries = on_disk
+            .into_iter()
+            .filter(|entry| !self.synced_entries.contains(entry) && !self.entries.contains(entry))
+            .collect::<Vec<_>>();
+        if foreign_entries.is_empty() {
+            return;
+        }
+        let first_unsynced = self
+            .entries
+            .iter()
+            .position(|entry| !self.synced_entries.contains(entry))
+            .unwrap_or(self.entries.len());
+        self.entries.splice(first_unsynced..first_unsynced, foreign_entries);
+        if let Some(max_size) = self.max_size {
+            if self.len() > max_size {
+                self.entries.drain(0..(self.len() - max_size));
+            }
         }
     }
 
     pub fn load_from_file(path: PathBuf, max_size: Option<usize>) -> CommandList {
-        if let Some(mut file) = File::open(path.clone()).ok() {
-            let mut contents = String::new();
-            file.read_to_string(&mut contents).ok();
-            CommandList::deserialize(Some(path), max_size, &contents)
-        } else {
-            CommandList::new(Some(path), max_size)
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let mut list = CommandList::deserialize(Some(path), max_size, &contents);
+                list.synced_entries = list.entries.clone();
+                list
+            }
+            Err(err) => {
+                let mut list = CommandList::new(Some(path.clone()), max_size);
+                if err.kind() != io::ErrorKind::NotFound {
+                    list.io_error = Some(format!("could not read {}: {}", path.display(), err));
+                }
+                list
+            }
+        }
+    }
+
+    /// load the bookmarks from a toml file. If it doesn't exist yet, the bookmarks of the original format are
+    /// read from the legacy path instead, and saved in the new format right away.
+    pub fn load_bookmarks(path: PathBuf, legacy_path: PathBuf) -> CommandList {
+        let mut list = CommandList::new(Some(path.clone()), None);
+        list.format = FileFormat::BookmarksToml;
+        match fs::read_to_string(&path) {
+            Ok(contents) => match bookmarks::deserialize_bookmarks(&contents) {
+                Ok(entries) => {
+                    list.entries = entries;
+                    list.synced_entries = list.entries.clone();
+                }
+                Err(err) => list.io_error = Some(format!("could not read {}: {}", path.display(), err)),
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                if legacy_path.exists() {
+                    list.entries = CommandList::load_from_file(legacy_path, None).entries;
+                    list.write_to_file();
+                }
+            }
+            Err(err) => list.io_error = Some(format!("could not read {}: {}", path.display(), err)),
+        }
+        list
+    }
+}
+
+fn parse_file(format: FileFormat, contents: &str) -> Result<Vec<CommandEntry>, String> {
+    match format {
+        FileFormat::Lines => Ok(CommandList::deserialize(None, None, contents).entries),
+        FileFormat::BookmarksToml => bookmarks::deserialize_bookmarks(contents),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// write the contents to a temporary file first, and move it over the target,
+/// so the file is never left half-written. If the path is a symlink, the file it points to is replaced,
+/// and an existing file keeps its permissions.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let (path, permissions) = match fs::canonicalize(path) {
+        Ok(target) => {
+            let permissions = fs::metadata(&target)?.permissions();
+            (target, Some(permissions))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (path.to_path_buf(), None),
+        Err(err) => return Err(err),
+    };
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name(&path)));
+    let mut temp_file = File::create(&temp_path)?;
+    if let Some(permissions) = permissions {
+        temp_file.set_permissions(permissions)?;
+    }
+    temp_file.write_all(contents.as_bytes())?;
+    temp_file.sync_all()?;
+    fs::rename(&temp_path, &path)
+}
+
+/// an exclusive advisory lock on a file, released when dropped.
+/// A separate lock file is used, as the locked file itself gets replaced on every write.
+struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    fn acquire(path: &Path) -> io::Result<FileLock> {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FileLock { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(lines: &[&str]) -> CommandEntry {
+        CommandEntry::new(lines.iter().map(|line| line.to_string()).collect())
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut list = CommandList::new(None, None);
+        list.push(entry(&["cat", "| grep foo"]));
+        list.push(entry(&["echo", "", "---", "@not metadata", "\\escaped"]));
+        list.record_run(
+            &entry(&["cat", "| grep foo"]),
+            RunSummary {
+                status: RunStatus::Exited(1),
+                duration: Duration::from_millis(42),
+                stdout_bytes: 0,
+                stdout_lines: 0,
+            },
+        );
+        list.entries[0].metadata.cwd = Some("/tmp/with\nnewline\\n".to_string());
+
+        let read = CommandList::deserialize(None, None, &list.serialize());
+        assert_eq!(read.len(), 2);
+        for (read, original) in read.entries.iter().zip(list.entries.iter()) {
+            assert_eq!(read.lines(), original.lines());
+            assert_eq!(read.metadata, original.metadata);
         }
+        assert_eq!(read.entries[0].metadata.run_count, 1);
+        assert_eq!(read.entries[0].metadata.exit_status, Some(RunStatus::Exited(1)));
+        assert!(read.entries[1].metadata.is_empty());
+    }
+
+    #[test]
+    fn test_run_count_continues() {
+        let summary = RunSummary {
+            status: RunStatus::Exited(0),
+            duration: Duration::from_millis(1),
+            stdout_bytes: 0,
+            stdout_lines: 0,
+        };
+        let mut list = CommandList::new(None, None);
+        list.push(entry(&["ls | wc"]));
+        list.record_run(&entry(&["ls | wc"]), summary);
+        list.push(entry(&["cat x"]));
+        list.record_run(&entry(&["cat x"]), summary);
+        list.push(entry(&["ls | wc"]));
+        list.record_run(&entry(&["ls | wc"]), summary);
+
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.get_at(2).unwrap().metadata.run_count, 2);
+        assert_eq!(list.get_at(1).unwrap().metadata.run_count, 1);
+    }
+
+    #[test]
+    fn test_import() {
+        let mut list = CommandList::new(None, Some(4));
+        list.push(entry(&["ls | wc"]));
+        list.push(entry(&["cat x | sort"]));
+        let imported = vec![entry(&["a | b"]), entry(&["c | d"]), entry(&["ls | wc"]), entry(&["e | f"]), entry(&["c | d"])];
+        assert_eq!(list.import(imported), 2);
+        let entries = list.entries().iter().map(|entry| entry.as_string()).collect::<Vec<_>>();
+        assert_eq!(entries, vec!["e | f", "c | d", "ls | wc", "cat x | sort"]);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pipr-test-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir.join("history")
+    }
+
+    #[test]
+    fn test_concurrent_writes_are_merged() {
+        let path = temp_path("merge");
+        let mut first = CommandList::load_from_file(path.clone(), None);
+        let mut second = CommandList::load_from_file(path.clone(), None);
+        first.push(entry(&["ls | wc"]));
+        second.push(entry(&["cat x | sort"]));
+        first.push(entry(&["ps | grep y"]));
+        assert_eq!(first.io_error, None);
+
+        let read = CommandList::load_from_file(path.clone(), None);
+        let entries = read.entries().iter().map(|entry| entry.as_string()).collect::<Vec<_>>();
+        assert_eq!(entries, vec!["ls | wc", "cat x | sort", "ps | grep y"]);
+
+        // entries removed in one instance are not brought back from the file
+        first.remove_entry(&entry(&["ls | wc"]));
+        assert_eq!(CommandList::load_from_file(path.clone(), None).len(), 2);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_symlinks_and_permissions_are_kept() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let path = temp_path("symlink");
+        let target = path.with_file_name("dotfiles-history");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&target, "").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o600)).unwrap();
+        symlink(&target, &path).unwrap();
+
+        let mut list = CommandList::load_from_file(path.clone(), None);
+        list.push(entry(&["ls | wc"]));
+        assert_eq!(list.io_error, None);
+        assert!(fs::symlink_metadata(&path).unwrap().file_type().is_symlink());
+        assert!(fs::read_to_string(&target).unwrap().contains("ls | wc"));
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o600);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_bookmarks_are_migrated() {
+        let legacy_path = temp_path("bookmarks");
+        fs::create_dir_all(legacy_path.parent().unwrap()).unwrap();
+        fs::write(&legacy_path, "ls\n---\ncat\n| sort\n").unwrap();
+        let path = legacy_path.with_file_name("bookmarks.toml");
+
+        let mut bookmarks = CommandList::load_bookmarks(path.clone(), legacy_path.clone());
+        assert_eq!(bookmarks.len(), 2);
+        assert!(fs::read_to_string(&path).unwrap().contains("[[bookmark]]"));
+
+        // once the toml file exists, the legacy file is ignored
+        bookmarks.remove_entry(&entry(&["ls"]));
+        assert_eq!(CommandList::load_bookmarks(path.clone(), legacy_path.clone()).len(), 1);
+        fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_write_errors_are_reported() {
+        let mut list = CommandList::new(Some(PathBuf::from("/dev/null/history")), None);
+        list.push(entry(&["ls"]));
+        assert!(list.io_error.as_ref().unwrap().starts_with("could not save /dev/null/history"));
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn test_read_original_format() {
+        let read = CommandList::deserialize(None, Some(2), "ls\n---\ncat\n\n| grep x\n---\ncat\n| grep x\n---\nsort\n");
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.entries[0].lines(), &vec!["cat".to_string(), "| grep x".to_string()]);
+        assert_eq!(read.entries[1].as_string(), "sort");
+        assert!(read.entries.iter().all(|entry| entry.metadata.is_empty()));
+    }
+
+    #[test]
+    fn test_unknown_metadata_is_ignored() {
+        let read = CommandList::deserialize(None, None, "#pipr-commandlist v2\n@runs 3\n@future value\n@status bogus\nls");
+        assert_eq!(read.entries[0].as_string(), "ls");
+        assert_eq!(read.entries[0].metadata.run_count, 3);
+        assert_eq!(read.entries[0].metadata.exit_status, None);
     }
 }
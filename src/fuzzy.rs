@@ -0,0 +1,101 @@
+/// score for every matched character
+const MATCH_SCORE: i64 = 1;
+/// bonus for a character matched directly after the previous one
+const CONSECUTIVE_BONUS: i64 = 8;
+/// bonus for a character matched at the start of a word
+const WORD_START_BONUS: i64 = 4;
+/// maximum penalty for the characters skipped between two matched characters
+const MAX_GAP_PENALTY: i64 = 5;
+
+/// a fuzzy match of a query in some text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// higher is better
+    pub score: i64,
+    /// the char indices of the matched characters in the text
+    pub positions: Vec<usize>,
+}
+
+/// match the characters of the query in order, but not necessarily next to each other, against the text.
+/// The query is matched case-insensitively, unless it contains uppercase characters.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<FuzzyMatch> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let normalize = |c: char| if case_sensitive { c } else { c.to_lowercase().next().unwrap_or(c) };
+    let query = query.chars().map(normalize).collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let normalized_text = text.iter().map(|&c| normalize(c)).collect::<Vec<_>>();
+
+    let first = match query.first() {
+        Some(&first) => first,
+        None => {
+            return Some(FuzzyMatch {
+                score: 0,
+                positions: Vec::new(),
+            })
+        }
+    };
+
+    // try every possible start, as matching greedily from the first occurrence may miss better matches
+    (0..text.len())
+        .filter(|&start| normalized_text[start] == first)
+        .filter_map(|start| {
+            let mut positions = vec![start];
+            for &c in &query[1..] {
+                let previous = *positions.last().unwrap();
+                let next = (previous + 1..text.len()).find(|&idx| normalized_text[idx] == c)?;
+                positions.push(next);
+            }
+            Some(FuzzyMatch {
+                score: score(&text, &positions),
+                positions,
+            })
+        })
+        .fold(None, |best: Option<FuzzyMatch>, current| match best {
+            Some(best) if best.score >= current.score => Some(best),
+            _ => Some(current),
+        })
+}
+
+fn score(text: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    for (n, &pos) in positions.iter().enumerate() {
+        score += MATCH_SCORE;
+        if pos == 0 || !text[pos - 1].is_alphanumeric() {
+            score += WORD_START_BONUS;
+        }
+        if n > 0 {
+            let gap = (pos - positions[n - 1] - 1) as i64;
+            score += if gap == 0 { CONSECUTIVE_BONUS } else { -gap.min(MAX_GAP_PENALTY) };
+        }
+    }
+    score
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(fuzzy_match("grp", "cat | grep x").unwrap().positions, vec![6, 7, 9]);
+        assert_eq!(fuzzy_match("", "cat").unwrap().positions, Vec::<usize>::new());
+        assert_eq!(fuzzy_match("gerp", "grep"), None);
+        assert_eq!(fuzzy_match("x", ""), None);
+        // later occurrences are found if they match better
+        assert_eq!(fuzzy_match("sed", "s e sed").unwrap().positions, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_smart_case() {
+        assert!(fuzzy_match("grep", "GREP").is_some());
+        assert!(fuzzy_match("Grep", "grep").is_none());
+        assert!(fuzzy_match("Grep", "Grep").is_some());
+    }
+
+    #[test]
+    fn test_scoring() {
+        let score = |query, text| fuzzy_match(query, text).unwrap().score;
+        assert!(score("sed", "sed -r") > score("sed", "s e d"));
+        assert!(score("gb", "grep bar") > score("gb", "ogre obey"));
+    }
+}
@@ -0,0 +1,251 @@
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::commandlist::CommandEntry;
+use crate::shell_lexer;
+
+/// the history file formats of the supported shells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellHistoryFormat {
+    /// one command per line, optionally preceded by `#<timestamp>` lines. Other lines starting with `#` are commands too.
+    Bash,
+    /// zsh's extended history, `: <timestamp>:<duration>;<command>`
+    Zsh,
+    /// fish's yaml-like format, `- cmd: <command>` followed by `  when: <timestamp>`
+    Fish,
+}
+
+impl ShellHistoryFormat {
+    /// guess the format from the first non-empty line of the file
+    pub fn detect(contents: &str) -> ShellHistoryFormat {
+        match contents.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) if line.starts_with("- cmd: ") => ShellHistoryFormat::Fish,
+            Some(line) if parse_zsh_extended_line(line).is_some() => ShellHistoryFormat::Zsh,
+            _ => ShellHistoryFormat::Bash,
+        }
+    }
+}
+
+/// a command read from a shell history file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedCommand {
+    pub command: String,
+    pub timestamp: Option<DateTime<Local>>,
+}
+
+impl ImportedCommand {
+    /// commands with at least one pipe are the ones that are interesting in pipr
+    pub fn is_pipeline(&self) -> bool {
+        shell_lexer::pipeline_stages(&self.command).len() > 1
+    }
+
+    pub fn into_entry(self) -> CommandEntry {
+        let mut entry = CommandEntry::new(self.command.lines().map(|line| line.to_owned()).collect());
+        entry.metadata.timestamp = self.timestamp;
+        entry
+    }
+}
+
+/// read all commands of a shell history file, oldest first
+pub fn parse_shell_history(format: ShellHistoryFormat, contents: &str) -> Vec<ImportedCommand> {
+    match format {
+        ShellHistoryFormat::Bash => parse_bash_history(contents),
+        ShellHistoryFormat::Zsh => parse_zsh_history(contents),
+        ShellHistoryFormat::Fish => parse_fish_history(contents),
+    }
+}
+
+/// the byte zsh puts in front of special bytes in its history file, which are then xor'ed with 0x20
+const ZSH_META: u8 = 0x83;
+
+/// read the pipelines of a shell history file as entries for pipr's history, oldest first.
+/// History files aren't necessarily valid UTF-8, so invalid bytes are replaced.
+pub fn import_pipelines(contents: &[u8]) -> Vec<CommandEntry> {
+    let format = ShellHistoryFormat::detect(&String::from_utf8_lossy(contents));
+    let contents = match format {
+        ShellHistoryFormat::Zsh => String::from_utf8_lossy(&unmetafy_zsh(contents)).to_string(),
+        _ => String::from_utf8_lossy(contents).to_string(),
+    };
+    parse_shell_history(format, &contents)
+        .into_iter()
+        .filter(ImportedCommand::is_pipeline)
+        .map(ImportedCommand::into_entry)
+        .collect()
+}
+
+/// undo the escaping zsh applies to bytes of non-ASCII characters in its history file
+fn unmetafy_zsh(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            ZSH_META => result.extend(bytes.next().map(|next| next ^ 0x20)),
+            byte => result.push(byte),
+        }
+    }
+    result
+}
+
+fn timestamp_from_secs(secs: &str) -> Option<DateTime<Local>> {
+    Local.timestamp_opt(secs.trim().parse().ok()?, 0).single()
+}
+
+fn parse_bash_history(contents: &str) -> Vec<ImportedCommand> {
+    let mut commands = Vec::new();
+    let mut timestamp = None;
+    for line in contents.lines() {
+        let secs = line.strip_prefix('#').filter(|secs| !secs.is_empty() && secs.chars().all(|c| c.is_ascii_digit()));
+        if let Some(secs) = secs {
+            // with HISTTIMEFORMAT set, bash writes the time of each command on the line before it
+            timestamp = timestamp_from_secs(secs);
+        } else if !line.trim().is_empty() {
+            commands.push(ImportedCommand {
+                command: line.to_owned(),
+                timestamp: timestamp.take(),
+            });
+        }
+    }
+    commands
+}
+
+/// split a line of zsh's extended history into the timestamp and the command
+fn parse_zsh_extended_line(line: &str) -> Option<(&str, &str)> {
+    let (meta, command) = line.strip_prefix(": ")?.split_once(';')?;
+    let (timestamp, duration) = meta.split_once(':')?;
+    if timestamp.chars().all(|c| c.is_ascii_digit()) && duration.chars().all(|c| c.is_ascii_digit()) {
+        Some((timestamp, command))
+    } else {
+        None
+    }
+}
+
+fn parse_zsh_history(contents: &str) -> Vec<ImportedCommand> {
+    let mut commands: Vec<ImportedCommand> = Vec::new();
+    let mut continues = false;
+    for line in contents.lines() {
+        let (timestamp, command) = match parse_zsh_extended_line(line) {
+            Some((timestamp, command)) if !continues => (timestamp_from_secs(timestamp), command),
+            _ => (None, line),
+        };
+        // multi-line commands are written with a backslash at the end of every line but the last
+        let (command, next_continues) = match command.strip_suffix('\\') {
+            Some(command) => (command, true),
+            None => (command, false),
+        };
+        match commands.last_mut() {
+            Some(last) if continues => {
+                last.command.push('\n');
+                last.command.push_str(command);
+            }
+            _ => commands.push(ImportedCommand {
+                command: command.to_owned(),
+                timestamp,
+            }),
+        }
+        continues = next_continues;
+    }
+    commands.retain(|command| !command.command.trim().is_empty());
+    commands
+}
+
+fn parse_fish_history(contents: &str) -> Vec<ImportedCommand> {
+    let mut commands: Vec<ImportedCommand> = Vec::new();
+    for line in contents.lines() {
+        if let Some(command) = line.strip_prefix("- cmd: ") {
+            commands.push(ImportedCommand {
+                command: unescape_fish(command),
+                timestamp: None,
+            });
+        } else if let Some(secs) = line.trim_start().strip_prefix("when: ") {
+            if let Some(last) = commands.last_mut() {
+                last.timestamp = timestamp_from_secs(secs);
+            }
+        }
+    }
+    commands.retain(|command| !command.command.trim().is_empty());
+    commands
+}
+
+/// fish writes newlines in commands as `\n` and backslashes as `\\`
+fn unescape_fish(command: &str) -> String {
+    let mut result = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                result.push('\\');
+                chars.next();
+            }
+            (c, _) => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn commands(format: ShellHistoryFormat, contents: &str) -> Vec<String> {
+        parse_shell_history(format, contents).into_iter().map(|c| c.command).collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(ShellHistoryFormat::detect("ls\ncat x | wc"), ShellHistoryFormat::Bash);
+        assert_eq!(ShellHistoryFormat::detect(": 1600000000:0;ls"), ShellHistoryFormat::Zsh);
+        assert_eq!(ShellHistoryFormat::detect("\n- cmd: ls\n  when: 1600000000"), ShellHistoryFormat::Fish);
+    }
+
+    #[test]
+    fn test_bash() {
+        let parsed = parse_shell_history(ShellHistoryFormat::Bash, "ls\n#1600000000\ncat x | wc -l\n\n");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].timestamp, None);
+        assert_eq!(parsed[1].command, "cat x | wc -l");
+        assert_eq!(parsed[1].timestamp.unwrap().timestamp(), 1_600_000_000);
+
+        // comments typed at the prompt are commands, not timestamps
+        let history = "#1600000000\n# grep x | sort\n#!/bin/sh\nls | wc";
+        assert_eq!(commands(ShellHistoryFormat::Bash, history), vec!["# grep x | sort", "#!/bin/sh", "ls | wc"]);
+        let parsed = parse_shell_history(ShellHistoryFormat::Bash, history);
+        assert_eq!(parsed[0].timestamp.unwrap().timestamp(), 1_600_000_000);
+        assert_eq!(parsed[2].timestamp, None);
+    }
+
+    #[test]
+    fn test_zsh() {
+        let history = ": 1600000000:0;ls\n: 1600000001:2;cat x \\\n| grep y\n: 1600000002:0;echo a";
+        assert_eq!(commands(ShellHistoryFormat::Zsh, history), vec!["ls", "cat x \n| grep y", "echo a"]);
+        let parsed = parse_shell_history(ShellHistoryFormat::Zsh, history);
+        assert_eq!(parsed[1].timestamp.unwrap().timestamp(), 1_600_000_001);
+    }
+
+    #[test]
+    fn test_fish() {
+        let history = "- cmd: ls\n  when: 1600000000\n- cmd: echo 'a\\\\b' \\n| tr a b\n  when: 1600000001\n  paths:\n    - x";
+        assert_eq!(commands(ShellHistoryFormat::Fish, history), vec!["ls", "echo 'a\\b' \n| tr a b"]);
+        let parsed = parse_shell_history(ShellHistoryFormat::Fish, history);
+        assert_eq!(parsed[1].timestamp.unwrap().timestamp(), 1_600_000_001);
+    }
+
+    #[test]
+    fn test_import_pipelines() {
+        let imported = import_pipelines(b"ls\ncat x | wc -l\necho '|'\nps aux | grep foo | sort");
+        let imported = imported.iter().map(|entry| entry.as_string()).collect::<Vec<_>>();
+        assert_eq!(imported, vec!["cat x | wc -l", "ps aux | grep foo | sort"]);
+    }
+
+    #[test]
+    fn test_import_non_utf8() {
+        // zsh writes `ё` (0xd1 0x91) with the second byte escaped
+        let imported = import_pipelines(b": 1600000000:0;echo \xd1\x83\xb1 | wc -c\n");
+        assert_eq!(imported[0].as_string(), "echo \u{451} | wc -c");
+        let imported = import_pipelines(b"ls \xff | wc\ncat | sort\n");
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].as_string(), "ls \u{fffd} | wc");
+    }
+}
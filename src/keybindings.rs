@@ -0,0 +1,463 @@
+use crate::lineeditor::EditorEvent;
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// everything a key can be bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    ToggleHelp,
+    ToggleAutoeval,
+    ToggleParanoidHistory,
+    ToggleHistory,
+    ToggleBookmarks,
+    OpenHelpViewer,
+    OpenOutputViewer,
+    CacheCommandPart,
+    ToggleStageInspector,
+    ToggleSampleInput,
+    CancelExecution,
+    SaveBookmark,
+    Execute,
+    Quit,
+    Autocomplete,
+    HistoryPrev,
+    HistoryNext,
+    HistorySearch,
+    ClearToHistory,
+    InsertSnippet,
+    Edit(EditorEvent),
+}
+
+/// the name of every action as used in the config file, and its description in the help window.
+/// Actions without a description are not listed in the help window.
+const ACTIONS: &[(&str, Action, &str)] = &[
+    ("toggle_help", Action::ToggleHelp, "Show/hide help"),
+    ("toggle_autoeval", Action::ToggleAutoeval, "Toggle autoeval"),
+    (
+        "toggle_paranoid_history",
+        Action::ToggleParanoidHistory,
+        "Toggle Paranoid history (fills up history in autoeval)",
+    ),
+    ("toggle_history", Action::ToggleHistory, "Show/hide history"),
+    ("toggle_bookmarks", Action::ToggleBookmarks, "Show/hide bookmarks"),
+    ("open_help_viewer", Action::OpenHelpViewer, "Open helpviewer"),
+    ("open_output_viewer", Action::OpenOutputViewer, "Open outputviewer"),
+    (
+        "cache_command_part",
+        Action::CacheCommandPart,
+        "When the cursor is on a `|` symbol, cache the output of everything before that |",
+    ),
+    (
+        "toggle_stage_inspector",
+        Action::ToggleStageInspector,
+        "Show/hide the output of every stage of the pipeline",
+    ),
+    (
+        "toggle_sample_input",
+        Action::ToggleSampleInput,
+        "Open the sample input (piped into the command), or switch between it and the command",
+    ),
+    ("cancel_execution", Action::CancelExecution, "Cancel the running command"),
+    ("save_bookmark", Action::SaveBookmark, "Save bookmark"),
+    ("execute", Action::Execute, "Run the command"),
+    ("quit", Action::Quit, "Quit"),
+    ("autocomplete", Action::Autocomplete, "Complete the path under the cursor"),
+    ("history_prev", Action::HistoryPrev, "Previous in history"),
+    ("history_next", Action::HistoryNext, "Next in history"),
+    (
+        "history_search",
+        Action::HistorySearch,
+        "Search the history (again for older matches, Return to accept)",
+    ),
+    ("clear_to_history", Action::ClearToHistory, "Clear Command, saving it in the history"),
+    (
+        "insert_snippet",
+        Action::InsertSnippet,
+        "Insert snippet (press corresponding key to choose)",
+    ),
+    ("new_line", Action::Edit(EditorEvent::NewLine), "Newline"),
+    ("clear", Action::Edit(EditorEvent::Clear), "Clear Command"),
+    ("undo", Action::Edit(EditorEvent::Undo), "Undo"),
+    ("redo", Action::Edit(EditorEvent::Redo), "Redo"),
+    ("home", Action::Edit(EditorEvent::Home), "Go to the start of the line"),
+    ("end", Action::Edit(EditorEvent::End), "Go to the end of the line"),
+    ("go_word_left", Action::Edit(EditorEvent::GoWordLeft), "Move one word back"),
+    ("go_word_right", Action::Edit(EditorEvent::GoWordRight), "Move one word forward"),
+    ("kill_to_line_end", Action::Edit(EditorEvent::KillToLineEnd), "Cut to the end of the line"),
+    ("kill_word_forward", Action::Edit(EditorEvent::KillWordForward), "Cut the next word"),
+    ("kill_word_back", Action::Edit(EditorEvent::KillWordBack), "Cut the previous word"),
+    ("yank", Action::Edit(EditorEvent::Yank), "Paste the last cut text"),
+    (
+        "yank_pop",
+        Action::Edit(EditorEvent::YankPop),
+        "After pasting, cycle through older cut texts",
+    ),
+    ("transpose_chars", Action::Edit(EditorEvent::TransposeChars), "Swap the characters before the cursor"),
+    ("upcase_word", Action::Edit(EditorEvent::UpcaseWord), "Uppercase the next word"),
+    ("downcase_word", Action::Edit(EditorEvent::DowncaseWord), "Lowercase the next word"),
+    ("capitalize_word", Action::Edit(EditorEvent::CapitalizeWord), "Capitalize the next word"),
+    ("copy", Action::Edit(EditorEvent::Copy), "Copy the selected text"),
+    ("cut", Action::Edit(EditorEvent::Cut), "Cut the selected text"),
+    ("paste", Action::Edit(EditorEvent::Paste), "Paste the last copied or cut text"),
+    ("select_left", Action::Edit(EditorEvent::SelectLeft), "Select text"),
+    ("select_right", Action::Edit(EditorEvent::SelectRight), "Select text"),
+    ("select_up", Action::Edit(EditorEvent::SelectUp), "Select text"),
+    ("select_down", Action::Edit(EditorEvent::SelectDown), "Select text"),
+    ("select_home", Action::Edit(EditorEvent::SelectHome), "Select to the start of the line"),
+    ("select_end", Action::Edit(EditorEvent::SelectEnd), "Select to the end of the line"),
+    ("go_left", Action::Edit(EditorEvent::GoLeft), ""),
+    ("go_right", Action::Edit(EditorEvent::GoRight), ""),
+    ("go_up", Action::Edit(EditorEvent::GoUp), ""),
+    ("go_down", Action::Edit(EditorEvent::GoDown), ""),
+    ("backspace", Action::Edit(EditorEvent::Backspace), ""),
+    ("delete", Action::Edit(EditorEvent::Delete), ""),
+];
+
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("f1", "toggle_help"),
+    ("f2", "toggle_autoeval"),
+    ("f3", "toggle_paranoid_history"),
+    ("f4", "toggle_history"),
+    ("ctrl+b", "toggle_bookmarks"),
+    ("f5", "open_help_viewer"),
+    ("f6", "open_output_viewer"),
+    ("f7", "cache_command_part"),
+    ("f8", "toggle_stage_inspector"),
+    ("f9", "toggle_sample_input"),
+    ("ctrl+g", "cancel_execution"),
+    ("ctrl+s", "save_bookmark"),
+    ("enter", "execute"),
+    ("esc", "quit"),
+    ("ctrl+q", "quit"),
+    ("ctrl+c", "quit"),
+    ("tab", "autocomplete"),
+    ("ctrl+p", "history_prev"),
+    ("ctrl+n", "history_next"),
+    ("ctrl+r", "history_search"),
+    ("ctrl+x", "clear_to_history"),
+    ("ctrl+v", "insert_snippet"),
+    ("alt+enter", "new_line"),
+    ("insert", "new_line"),
+    ("ctrl+u", "clear"),
+    ("ctrl+z", "undo"),
+    ("alt+z", "redo"),
+    ("ctrl+a", "home"),
+    ("home", "home"),
+    ("ctrl+e", "end"),
+    ("end", "end"),
+    ("alt+b", "go_word_left"),
+    ("ctrl+left", "go_word_left"),
+    ("alt+left", "go_word_left"),
+    ("alt+f", "go_word_right"),
+    ("ctrl+right", "go_word_right"),
+    ("alt+right", "go_word_right"),
+    ("ctrl+k", "kill_to_line_end"),
+    ("alt+d", "kill_word_forward"),
+    ("ctrl+w", "kill_word_back"),
+    ("alt+backspace", "kill_word_back"),
+    ("ctrl+y", "yank"),
+    ("alt+y", "yank_pop"),
+    ("ctrl+t", "transpose_chars"),
+    ("alt+u", "upcase_word"),
+    ("alt+l", "downcase_word"),
+    ("alt+c", "capitalize_word"),
+    ("alt+w", "copy"),
+    ("alt+x", "cut"),
+    ("alt+v", "paste"),
+    ("shift+left", "select_left"),
+    ("shift+right", "select_right"),
+    ("shift+up", "select_up"),
+    ("shift+down", "select_down"),
+    ("shift+home", "select_home"),
+    ("shift+end", "select_end"),
+    ("left", "go_left"),
+    ("ctrl+f", "go_right"),
+    ("right", "go_right"),
+    ("up", "go_up"),
+    ("down", "go_down"),
+    ("backspace", "backspace"),
+    ("ctrl+h", "backspace"),
+    ("delete", "delete"),
+    ("ctrl+d", "delete"),
+];
+
+const HELP_FOOTER: &str = "
+disable a line by starting it with a #
+this will simply exclude the line from the executed command.
+
+Config file is in
+~/.config/pipr/pipr.toml";
+
+impl FromStr for Action {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIONS
+            .iter()
+            .find(|(name, _, _)| *name == s)
+            .map(|(_, action, _)| action.clone())
+            .ok_or_else(|| format!("unknown action `{}`", s))
+    }
+}
+
+/// a key together with the modifiers that are held down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    /// create a chord from a key event. Shift is already contained in characters, so it is ignored for them.
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> KeyChord {
+        let code = match code {
+            KeyCode::Char('\r') | KeyCode::Char('\n') => KeyCode::Enter,
+            _ => code,
+        };
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        KeyChord { code, modifiers }
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = String;
+    /// parse a chord like `ctrl+b`, `alt+enter` or `f5`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("unknown key `{}`", s);
+        let mut parts = s.split('+').collect::<Vec<_>>();
+        // `+` itself (as in `ctrl++`) splits into two empty parts
+        if parts.ends_with(&["", ""]) {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let (key, modifier_names) = parts.split_last().ok_or_else(invalid)?;
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in modifier_names {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+        }
+        let lowercase_key = key.to_lowercase();
+        let code = match lowercase_key.as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "space" => KeyCode::Char(' '),
+            _ if key.chars().count() == 1 => KeyCode::Char(key.chars().next().unwrap()),
+            _ if lowercase_key.starts_with('f') => {
+                KeyCode::F(lowercase_key[1..].parse::<u8>().ok().filter(|n| (1..=24).contains(n)).ok_or_else(invalid)?)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(KeyChord::new(code, modifiers))
+    }
+}
+
+impl Display for KeyChord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) if self.modifiers.is_empty() => write!(f, "{}", c),
+            KeyCode::Char(c) => write!(f, "{}", c.to_uppercase()),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::Enter => write!(f, "Return"),
+            KeyCode::BackTab => write!(f, "Shift+Tab"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// the keybindings, read from the `[keybindings]` section of the config file
+/// on top of the default bindings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keybindings {
+    bindings: HashMap<KeyChord, Action>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(chord, action)| (chord.parse().unwrap(), action.parse().unwrap()))
+            .collect();
+        Keybindings { bindings }
+    }
+}
+
+impl Keybindings {
+    /// apply the bindings from the config file to the default bindings.
+    /// Binding a key to `"none"` removes its default binding.
+    pub fn with_overrides(overrides: &HashMap<String, String>) -> Result<Keybindings, String> {
+        let mut keybindings = Keybindings::default();
+        for (chord, action) in overrides {
+            let chord = chord.parse::<KeyChord>()?;
+            if action == "none" {
+                keybindings.bindings.remove(&chord);
+            } else {
+                keybindings.bindings.insert(chord, action.parse()?);
+            }
+        }
+        Ok(keybindings)
+    }
+
+    pub fn action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        self.bindings.get(&KeyChord::new(code, modifiers)).cloned()
+    }
+
+    /// the editor event a key triggers. Characters without a binding are inserted.
+    pub fn editor_event(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<EditorEvent> {
+        match self.action(code, modifiers) {
+            Some(Action::Edit(event)) => Some(event),
+            Some(_) => None,
+            None => match code {
+                KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::NewCharacter(c)),
+                _ => None,
+            },
+        }
+    }
+
+    /// the keys bound to an action, sorted by name
+    pub fn keys_for(&self, action: &Action) -> Vec<KeyChord> {
+        let mut keys = self
+            .bindings
+            .iter()
+            .filter(|(_, bound)| *bound == action)
+            .map(|(&chord, _)| chord)
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|chord| chord.to_string());
+        keys
+    }
+
+    /// the text of the help window, listing the current bindings.
+    /// Consecutive actions with the same description share a line.
+    pub fn help_text(&self) -> String {
+        let mut lines: Vec<(Vec<String>, &str)> = Vec::new();
+        for (_, action, description) in ACTIONS.iter().filter(|(_, _, description)| !description.is_empty()) {
+            let keys = self.keys_for(action).iter().map(|chord| chord.to_string()).collect::<Vec<_>>();
+            match lines.last_mut() {
+                Some((previous_keys, previous_description)) if previous_description == description => {
+                    previous_keys.extend(keys)
+                }
+                _ => lines.push((keys, *description)),
+            }
+        }
+        let lines = lines
+            .into_iter()
+            .map(|(keys, description)| (keys.join(", "), description))
+            .filter(|(keys, _)| !keys.is_empty())
+            .collect::<Vec<_>>();
+        let width = lines.iter().map(|(keys, _)| keys.len()).max().unwrap_or(0);
+        let mut text = String::new();
+        for (keys, description) in lines {
+            text.push_str(&format!("{:width$} {}\n", keys, description, width = width));
+        }
+        text.push_str(HELP_FOOTER);
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashmap;
+
+    #[test]
+    fn test_actions_are_documented() {
+        let config = crate::pipr_config::DEFAULT_CONFIG;
+        let documented = &config[config.find("# Actions:").unwrap()..config.find("[keybindings]").unwrap()];
+        let documented = documented
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty() && *word != "#")
+            .collect::<Vec<_>>();
+        for (name, _, _) in ACTIONS {
+            assert!(documented.contains(name), "action {} is missing in the default config", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_key_chord() {
+        assert_eq!("ctrl+b".parse(), Ok(KeyChord::new(KeyCode::Char('b'), KeyModifiers::CONTROL)));
+        assert_eq!(
+            "Alt+Return".parse(),
+            Ok(KeyChord::new(KeyCode::Enter, KeyModifiers::ALT))
+        );
+        assert_eq!("f12".parse(), Ok(KeyChord::new(KeyCode::F(12), KeyModifiers::NONE)));
+        assert_eq!("shift+tab".parse(), Ok(KeyChord::new(KeyCode::BackTab, KeyModifiers::NONE)));
+        assert_eq!("ctrl++".parse(), Ok(KeyChord::new(KeyCode::Char('+'), KeyModifiers::CONTROL)));
+        assert!("hyper+b".parse::<KeyChord>().is_err());
+        assert!("f0".parse::<KeyChord>().is_err());
+        assert!("ctrl+foo".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn test_display_key_chord() {
+        assert_eq!(KeyChord::new(KeyCode::Char('b'), KeyModifiers::CONTROL).to_string(), "Ctrl+B");
+        assert_eq!(KeyChord::new(KeyCode::Enter, KeyModifiers::ALT).to_string(), "Alt+Return");
+        assert_eq!(KeyChord::new(KeyCode::F(4), KeyModifiers::NONE).to_string(), "F4");
+        assert_eq!(KeyChord::new(KeyCode::Char('A'), KeyModifiers::SHIFT).to_string(), "A");
+    }
+
+    #[test]
+    fn test_overrides() {
+        let keybindings = Keybindings::with_overrides(&hashmap! {
+            "alt+h".to_string() => "toggle_history".to_string(),
+            "f4".to_string() => "none".to_string(),
+            "ctrl+b".to_string() => "go_left".to_string(),
+        })
+        .unwrap();
+        assert_eq!(keybindings.action(KeyCode::Char('h'), KeyModifiers::ALT), Some(Action::ToggleHistory));
+        assert_eq!(keybindings.action(KeyCode::F(4), KeyModifiers::NONE), None);
+        assert_eq!(
+            keybindings.editor_event(KeyCode::Char('b'), KeyModifiers::CONTROL),
+            Some(EditorEvent::GoLeft)
+        );
+        assert_eq!(
+            keybindings.editor_event(KeyCode::Char('B'), KeyModifiers::SHIFT),
+            Some(EditorEvent::NewCharacter('B'))
+        );
+        assert!(keybindings.help_text().contains("Alt+H"));
+        assert!(!keybindings.help_text().contains("F4"));
+
+        let unknown_key = Keybindings::with_overrides(&hashmap! { "ctrl+foo".to_string() => "quit".to_string() });
+        assert_eq!(unknown_key, Err("unknown key `ctrl+foo`".to_string()));
+        let unknown_action = Keybindings::with_overrides(&hashmap! { "f4".to_string() => "explode".to_string() });
+        assert_eq!(unknown_action, Err("unknown action `explode`".to_string()));
+    }
+
+    #[test]
+    fn test_default_help_text() {
+        let help_text = Keybindings::default().help_text();
+        assert!(help_text.contains("Ctrl+B"));
+        assert!(help_text.contains("Ctrl+C, Ctrl+Q, Esc"));
+        assert!(help_text.ends_with("~/.config/pipr/pipr.toml"));
+    }
+}
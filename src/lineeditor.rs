@@ -1,13 +1,45 @@
 use super::commandlist::*;
-use crossterm::event::{KeyCode, KeyModifiers};
+use crate::util::VecStringExt;
+use std::ops::Range;
 use unicode_width::*;
 
+/// maximum amount of undo steps that are remembered
+const UNDO_LIMIT: usize = 200;
+
+/// maximum amount of killed texts that are remembered
+const KILL_RING_SIZE: usize = 30;
+
 #[derive(Debug, Clone)]
 pub struct EditorState {
     lines: Vec<String>,
     pub cursor_line: usize,
     pub cursor_col: usize,
+    undo_stack: Vec<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+    /// true while characters are typed without anything else happening in between,
+    /// so they can be undone together.
+    is_inserting: bool,
+    /// killed (cut) texts, most recent last. Consecutive kills are merged into a single entry.
+    kill_ring: Vec<String>,
+    /// true if the previous event killed text
+    last_was_kill: bool,
+    /// the range of the text inserted by the previous yank, and the index of the yanked kill ring entry
+    last_yank: Option<(Range<usize>, usize)>,
+    /// the offset where the selection started, the selection spans from here to the cursor
+    selection_anchor: Option<usize>,
+    /// text that was copied or cut since the last call to `take_copied_text`
+    copied_text: Option<String>,
+}
+
+/// the content and cursor position of an editor at some point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    lines: Vec<String>,
+    cursor_line: usize,
+    cursor_col: usize,
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EditorEvent {
     NewCharacter(char),
     NewLine,
@@ -21,30 +53,29 @@ pub enum EditorEvent {
     Home,
     End,
     KillWordBack,
-}
-
-pub fn convert_keyevent_to_editorevent(code: KeyCode, modifiers: KeyModifiers) -> Option<EditorEvent> {
-    match code {
-        KeyCode::Left => Some(EditorEvent::GoLeft),
-        KeyCode::Right => Some(EditorEvent::GoRight),
-        KeyCode::Up => Some(EditorEvent::GoUp),
-        KeyCode::Down => Some(EditorEvent::GoDown),
-        KeyCode::Home => Some(EditorEvent::Home),
-        KeyCode::End => Some(EditorEvent::End),
-        KeyCode::Char('a') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::Home),
-        KeyCode::Char('e') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::End),
-        KeyCode::Char('u') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::Clear),
-        KeyCode::Char('w') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::KillWordBack),
-        KeyCode::Enter | KeyCode::Char('\r') | KeyCode::Char('\n') if modifiers.contains(KeyModifiers::ALT) => {
-            Some(EditorEvent::NewLine)
-        }
-        KeyCode::Char('h') if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorEvent::Backspace),
-        KeyCode::Insert => Some(EditorEvent::NewLine),
-        KeyCode::Char(c) => Some(EditorEvent::NewCharacter(c)),
-        KeyCode::Backspace => Some(EditorEvent::Backspace),
-        KeyCode::Delete => Some(EditorEvent::Delete),
-        _ => None,
-    }
+    Undo,
+    Redo,
+    GoWordLeft,
+    GoWordRight,
+    KillToLineEnd,
+    KillWordForward,
+    Yank,
+    YankPop,
+    TransposeChars,
+    UpcaseWord,
+    DowncaseWord,
+    CapitalizeWord,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    SelectHome,
+    SelectEnd,
+    Copy,
+    Cut,
+    Paste,
+    /// insert text that may contain line breaks at once, like a pasted command
+    InsertText(String),
 }
 
 impl EditorState {
@@ -53,6 +84,14 @@ impl EditorState {
             lines: vec![String::new()],
             cursor_line: 0,
             cursor_col: 0,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            is_inserting: false,
+            kill_ring: Vec::new(),
+            last_was_kill: false,
+            last_yank: None,
+            selection_anchor: None,
+            copied_text: None,
         }
     }
 
@@ -64,7 +103,14 @@ impl EditorState {
         self.set_content(entry.lines().clone());
     }
 
+    /// replace the content, moving the cursor to its end. This can be undone.
     pub fn set_content(&mut self, new_content: Vec<String>) {
+        let before = self.snapshot();
+        self.replace_content(new_content);
+        self.record_undo(before);
+    }
+
+    fn replace_content(&mut self, new_content: Vec<String>) {
         // prevent setting _no_ lines, which would crash
         self.lines = if new_content.is_empty() {
             vec![String::new()]
@@ -73,6 +119,7 @@ impl EditorState {
         };
         self.cursor_line = self.lines.len() - 1;
         self.cursor_col = self.current_line().len();
+        self.selection_anchor = None;
     }
 
     pub fn content_str(&self) -> String {
@@ -90,6 +137,123 @@ impl EditorState {
         self.lines[0].drain(..col);
     }
 
+    /// the byte offset of the cursor within the content joined with `\n`
+    pub fn cursor_offset(&self) -> usize {
+        self.lines.joined_offset_of(self.cursor_line, self.cursor_col)
+    }
+
+    /// move the cursor to a byte offset within the content joined with `\n`
+    pub fn set_cursor_offset(&mut self, offset: usize) {
+        let (cursor_line, cursor_col) = self.lines.position_of_joined_offset(offset);
+        self.cursor_line = cursor_line;
+        self.cursor_col = cursor_col;
+    }
+
+    /// replace a byte range of the content joined with `\n` with the given text,
+    /// moving the cursor to the end of the inserted text. This can be undone.
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        let before = self.snapshot();
+        self.replace_joined_range(range, text);
+        self.record_undo(before);
+    }
+
+    fn replace_joined_range(&mut self, range: Range<usize>, text: &str) {
+        self.selection_anchor = None;
+        let mut content = self.lines.join("\n");
+        content.replace_range(range.clone(), text);
+        self.lines = content.split('\n').map(|line| line.to_string()).collect();
+        self.set_cursor_offset(range.start + text.len());
+    }
+
+    /// the selected byte range of the content joined with `\n`, if anything is selected
+    pub fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.selection_anchor?;
+        let cursor = self.cursor_offset();
+        match anchor.cmp(&cursor) {
+            std::cmp::Ordering::Less => Some(anchor..cursor),
+            std::cmp::Ordering::Greater => Some(cursor..anchor),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    /// the text that was copied or cut since this was last called
+    pub fn take_copied_text(&mut self) -> Option<String> {
+        self.copied_text.take()
+    }
+
+    /// move the cursor, keeping the selection anchored where it was, or at the cursor if nothing was selected
+    fn extend_selection(&mut self, anchor: Option<usize>, movement: EditorEvent) {
+        let anchor = anchor.unwrap_or_else(|| self.cursor_offset());
+        self.apply_event(movement);
+        self.selection_anchor = Some(anchor);
+    }
+
+    /// remove a byte range of the content joined with `\n`, putting it into the kill ring.
+    /// Text killed right after another kill is added to the same kill ring entry.
+    fn kill_range(&mut self, range: Range<usize>, backwards: bool) {
+        let killed = self.lines.join("\n")[range.clone()].to_string();
+        self.add_to_kill_ring(killed, backwards);
+        self.replace_joined_range(range, "");
+    }
+
+    fn add_to_kill_ring(&mut self, killed: String, backwards: bool) {
+        if killed.is_empty() {
+            return;
+        }
+        match self.kill_ring.last_mut() {
+            Some(last) if self.last_was_kill && backwards => last.insert_str(0, &killed),
+            Some(last) if self.last_was_kill => last.push_str(&killed),
+            _ => {
+                if self.kill_ring.len() >= KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+                self.kill_ring.push(killed);
+            }
+        }
+        self.last_was_kill = true;
+    }
+
+    /// insert the kill ring entry at the given index at the cursor
+    fn yank(&mut self, ring_idx: usize) {
+        let text = self.kill_ring[ring_idx].clone();
+        let start = self.cursor_offset();
+        self.replace_joined_range(start..start, &text);
+        self.last_yank = Some((start..start + text.len(), ring_idx));
+    }
+
+    /// the offset of the end of the word after the given offset, like in readline
+    fn next_word_end(&self, offset: usize) -> usize {
+        let content = self.lines.join("\n");
+        let rest = &content[offset..];
+        let word_start = rest.find(char::is_alphanumeric).unwrap_or(rest.len());
+        let word_len = rest[word_start..].find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len() - word_start);
+        offset + word_start + word_len
+    }
+
+    /// the offset of the start of the word before the given offset, like in readline
+    fn prev_word_start(&self, offset: usize) -> usize {
+        let content = self.lines.join("\n");
+        let before = &content[..offset];
+        let word_end = before.rfind(char::is_alphanumeric).map(|idx| idx + 1).unwrap_or(0);
+        before[..word_end]
+            .rfind(|c: char| !c.is_alphanumeric())
+            .map(|idx| idx + before[idx..].chars().next().unwrap().len_utf8())
+            .unwrap_or(0)
+    }
+
+    /// change the case of the text from the cursor to the end of the current word, moving the cursor behind it
+    fn change_word_case(&mut self, change: impl Fn(&str) -> String) {
+        let start = self.cursor_offset();
+        let end = self.next_word_end(start);
+        let changed = change(&self.lines.join("\n")[start..end]);
+        self.replace_joined_range(start..end, &changed);
+    }
+
+    /// characters typed right after this are undone together with the previous change
+    pub fn group_following_inserts(&mut self) {
+        self.is_inserting = true;
+    }
+
     pub fn current_line(&self) -> &str {
         &self.lines[self.cursor_line]
     }
@@ -101,11 +265,61 @@ impl EditorState {
     /// insert a given string at the cursor position
     /// second argument determines if the cursor should be moved to the end of the inserted text or not.
     pub fn insert_at_cursor(&mut self, text: &str, move_cursor: bool) {
+        let before = self.snapshot();
+        self.selection_anchor = None;
         let cursor_col = self.cursor_col;
         self.current_line_mut().insert_str(cursor_col, &text);
         if move_cursor {
             self.cursor_col += text.len()
         }
+        self.record_undo(before);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            lines: self.lines.clone(),
+            cursor_line: self.cursor_line,
+            cursor_col: self.cursor_col,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.selection_anchor = None;
+        self.lines = snapshot.lines;
+        self.cursor_line = snapshot.cursor_line;
+        self.cursor_col = snapshot.cursor_col;
+    }
+
+    /// remember the given state as an undo step, if the content has changed since then
+    fn record_undo(&mut self, before: Snapshot) {
+        self.is_inserting = false;
+        if before.lines != self.lines {
+            self.push_undo(before);
+        }
+    }
+
+    fn push_undo(&mut self, snapshot: Snapshot) {
+        if self.undo_stack.len() >= UNDO_LIMIT {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(snapshot);
+        self.redo_stack.clear();
+    }
+
+    fn undo(&mut self) {
+        self.is_inserting = false;
+        if let Some(snapshot) = self.undo_stack.pop() {
+            self.redo_stack.push(self.snapshot());
+            self.restore(snapshot);
+        }
+    }
+
+    fn redo(&mut self) {
+        self.is_inserting = false;
+        if let Some(snapshot) = self.redo_stack.pop() {
+            self.undo_stack.push(self.snapshot());
+            self.restore(snapshot);
+        }
     }
 
     pub fn hovered_char(&self) -> Option<&str> {
@@ -149,7 +363,37 @@ impl EditorState {
     }
 
     pub fn apply_event(&mut self, event: EditorEvent) {
+        let selection = self.selection();
+        let selection_anchor = self.selection_anchor.take();
+        match event {
+            EditorEvent::Undo => return self.undo(),
+            EditorEvent::Redo => return self.redo(),
+            _ => {}
+        }
+        let previous_yank = self.last_yank.take();
+        let previous_was_kill = self.last_was_kill;
+        self.last_was_kill = false;
+
+        let before = self.snapshot();
+        let is_insert = matches!(event, EditorEvent::NewCharacter(_));
+        let continues_inserting = is_insert && self.is_inserting && selection.is_none();
+
+        // typing replaces the selected text
+        let replaces_selection = matches!(
+            event,
+            EditorEvent::NewCharacter(_)
+                | EditorEvent::NewLine
+                | EditorEvent::Backspace
+                | EditorEvent::Delete
+                | EditorEvent::Paste
+                | EditorEvent::InsertText(_)
+        );
+        if let (Some(selection), true) = (selection.clone(), replaces_selection) {
+            self.replace_joined_range(selection, "");
+        }
+
         match event {
+            EditorEvent::Backspace | EditorEvent::Delete if selection.is_some() => {}
             EditorEvent::NewCharacter(c) => {
                 let cursor_col = self.cursor_col;
                 self.current_line_mut().insert(cursor_col, c);
@@ -189,7 +433,11 @@ impl EditorState {
             }
 
             EditorEvent::Clear => {
-                self.set_content(vec![String::new()]);
+                // keep the cleared command in the kill ring, so it can be yanked back
+                let content = self.lines.join("\n");
+                self.last_was_kill = false;
+                self.add_to_kill_ring(content, false);
+                self.replace_content(vec![String::new()]);
             }
 
             EditorEvent::GoLeft => {
@@ -214,17 +462,109 @@ impl EditorState {
             EditorEvent::End => self.cursor_col = self.current_line().len(),
 
             EditorEvent::KillWordBack if !self.current_line().is_empty() => {
-                while let Some(c) = self.current_line().to_owned().get(self.prev_char_index()..self.cursor_col) {
-                    let cursor_col = self.prev_char_index();
-                    self.cursor_col = cursor_col;
-                    self.current_line_mut().remove(cursor_col);
+                let end = self.cursor_col;
+                while let Some(c) = self.current_line().get(self.prev_char_index()..self.cursor_col).map(str::to_owned) {
+                    self.cursor_col = self.prev_char_index();
                     if c == " " || c == "/" || c == "\\" || c == ":" || c == "_" || c == "-" || self.cursor_col == 0 {
                         break;
                     }
                 }
+                let line_offset = self.cursor_offset() - self.cursor_col;
+                self.last_was_kill = previous_was_kill;
+                self.kill_range(line_offset + self.cursor_col..line_offset + end, true);
+            }
+
+            EditorEvent::GoWordLeft => self.set_cursor_offset(self.prev_word_start(self.cursor_offset())),
+            EditorEvent::GoWordRight => self.set_cursor_offset(self.next_word_end(self.cursor_offset())),
+            EditorEvent::KillToLineEnd => {
+                let start = self.cursor_offset();
+                // at the end of a line, the newline is killed, joining the lines
+                let end = if self.cursor_col == self.current_line().len() && self.cursor_line < self.lines.len() - 1 {
+                    start + 1
+                } else {
+                    start + self.current_line().len() - self.cursor_col
+                };
+                self.last_was_kill = previous_was_kill;
+                self.kill_range(start..end, false);
+            }
+            EditorEvent::KillWordForward => {
+                let start = self.cursor_offset();
+                let end = self.next_word_end(start);
+                self.last_was_kill = previous_was_kill;
+                self.kill_range(start..end, false);
+            }
+            EditorEvent::Yank | EditorEvent::Paste if !self.kill_ring.is_empty() => self.yank(self.kill_ring.len() - 1),
+            EditorEvent::SelectLeft => self.extend_selection(selection_anchor, EditorEvent::GoLeft),
+            EditorEvent::SelectRight => self.extend_selection(selection_anchor, EditorEvent::GoRight),
+            EditorEvent::SelectUp => self.extend_selection(selection_anchor, EditorEvent::GoUp),
+            EditorEvent::SelectDown => self.extend_selection(selection_anchor, EditorEvent::GoDown),
+            EditorEvent::SelectHome => self.extend_selection(selection_anchor, EditorEvent::Home),
+            EditorEvent::SelectEnd => self.extend_selection(selection_anchor, EditorEvent::End),
+            EditorEvent::Copy => {
+                if let Some(selection) = selection {
+                    let text = self.lines.join("\n")[selection].to_string();
+                    self.add_to_kill_ring(text.clone(), false);
+                    self.last_was_kill = false;
+                    self.copied_text = Some(text);
+                    // keep the selection, like most editors do
+                    self.selection_anchor = selection_anchor;
+                }
+            }
+            EditorEvent::InsertText(text) => {
+                let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                let offset = self.cursor_offset();
+                self.replace_joined_range(offset..offset, &text);
+            }
+            EditorEvent::Cut => {
+                if let Some(selection) = selection {
+                    self.copied_text = Some(self.lines.join("\n")[selection.clone()].to_string());
+                    self.kill_range(selection, false);
+                }
             }
+            EditorEvent::YankPop => {
+                // replace the text that was just yanked with the previous kill ring entry
+                if let Some((range, ring_idx)) = previous_yank {
+                    self.replace_joined_range(range, "");
+                    self.yank((ring_idx + self.kill_ring.len() - 1) % self.kill_ring.len());
+                }
+            }
+            EditorEvent::TransposeChars if self.cursor_col > 0 && self.current_line().chars().count() > 1 => {
+                // at the end of the line, the last two characters are swapped
+                if self.cursor_col == self.current_line().len() {
+                    self.cursor_col = self.prev_char_index();
+                }
+                let second_start = self.cursor_col;
+                let second_end = self.next_char_index();
+                self.cursor_col = self.prev_char_index();
+                let first_start = self.cursor_col;
+                let line = self.current_line().to_string();
+                let swapped = format!("{}{}", &line[second_start..second_end], &line[first_start..second_start]);
+                self.current_line_mut().replace_range(first_start..second_end, &swapped);
+                self.cursor_col = second_end;
+            }
+            EditorEvent::UpcaseWord => self.change_word_case(|word| word.to_uppercase()),
+            EditorEvent::DowncaseWord => self.change_word_case(|word| word.to_lowercase()),
+            EditorEvent::CapitalizeWord => self.change_word_case(|word| {
+                let mut capitalized = String::new();
+                let mut seen_letter = false;
+                for c in word.chars() {
+                    if c.is_alphanumeric() && !seen_letter {
+                        seen_letter = true;
+                        capitalized.extend(c.to_uppercase());
+                    } else {
+                        capitalized.extend(c.to_lowercase());
+                    }
+                }
+                capitalized
+            }),
             _ => {}
         }
+
+        // consecutively typed characters are undone together
+        if self.lines != before.lines && !continues_inserting {
+            self.push_undo(before);
+        }
+        self.is_inserting = is_insert;
     }
 }
 
@@ -371,4 +711,173 @@ pub mod test {
         assert_eq!(le.cursor_line, 0);
         assert_eq!(*le.content_lines(), vec!["ab"]);
     }
+
+    #[test]
+    pub fn test_undo_redo() {
+        let mut le = EditorState::new();
+        le.apply_event(EditorEvent::NewCharacter('a'));
+        le.apply_event(EditorEvent::NewCharacter('b'));
+        le.apply_event(EditorEvent::GoLeft);
+        le.apply_event(EditorEvent::NewCharacter('c'));
+        assert_eq!(le.content_str(), "acb");
+
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(le.content_str(), "ab");
+        assert_eq!(le.cursor_col, 1);
+
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(le.content_str(), "");
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(le.content_str(), "");
+
+        le.apply_event(EditorEvent::Redo);
+        assert_eq!(le.content_str(), "ab");
+        assert_eq!(le.cursor_col, 1);
+        le.apply_event(EditorEvent::Redo);
+        assert_eq!(le.content_str(), "acb");
+        assert_eq!(le.cursor_col, 2);
+        le.apply_event(EditorEvent::Redo);
+        assert_eq!(le.content_str(), "acb");
+
+        // a new edit discards everything that could be redone
+        le.apply_event(EditorEvent::Undo);
+        le.apply_event(EditorEvent::Backspace);
+        le.apply_event(EditorEvent::Redo);
+        assert_eq!(le.content_str(), "b");
+    }
+
+    #[test]
+    pub fn test_undo_clear_and_load() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["cat foo".into(), "| grep bar".into()]);
+        le.apply_event(EditorEvent::Clear);
+        assert_eq!(le.content_str(), "");
+
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(*le.content_lines(), vec!["cat foo", "| grep bar"]);
+        assert_eq!((le.cursor_line, le.cursor_col), (1, 10));
+
+        le.load_commandentry(&CommandEntry::new(vec!["ls".into()]));
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(*le.content_lines(), vec!["cat foo", "| grep bar"]);
+    }
+
+    #[test]
+    pub fn test_word_movement_and_case() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["cat foo.txt | grep".into()]);
+        le.apply_event(EditorEvent::Home);
+        le.apply_event(EditorEvent::GoWordRight);
+        assert_eq!(le.cursor_col, 3);
+        le.apply_event(EditorEvent::GoWordRight);
+        assert_eq!(le.cursor_col, 7);
+        le.apply_event(EditorEvent::GoWordLeft);
+        assert_eq!(le.cursor_col, 4);
+        le.apply_event(EditorEvent::UpcaseWord);
+        assert_eq!(le.content_str(), "cat FOO.txt | grep");
+        assert_eq!(le.cursor_col, 7);
+        le.apply_event(EditorEvent::CapitalizeWord);
+        assert_eq!(le.content_str(), "cat FOO.Txt | grep");
+        le.apply_event(EditorEvent::Home);
+        le.apply_event(EditorEvent::DowncaseWord);
+        le.apply_event(EditorEvent::DowncaseWord);
+        assert_eq!(le.content_str(), "cat foo.Txt | grep");
+    }
+
+    #[test]
+    pub fn test_kill_and_yank() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["cat foo bar".into(), "| wc".into()]);
+        le.set_cursor_offset(4);
+        le.apply_event(EditorEvent::KillWordForward);
+        le.apply_event(EditorEvent::KillWordForward);
+        assert_eq!(le.content_lines().join("\n"), "cat \n| wc");
+        // at the end of the line, the newline is killed
+        le.apply_event(EditorEvent::KillToLineEnd);
+        assert_eq!(le.content_lines().len(), 1);
+        assert_eq!(le.content_lines().join("\n"), "cat | wc");
+
+        le.apply_event(EditorEvent::End);
+        le.apply_event(EditorEvent::Yank);
+        assert_eq!(le.content_lines().join("\n"), "cat | wcfoo bar\n");
+
+        le.apply_event(EditorEvent::Undo);
+        le.apply_event(EditorEvent::KillWordBack);
+        le.apply_event(EditorEvent::KillWordBack);
+        assert_eq!(le.content_lines().join("\n"), "cat");
+        le.apply_event(EditorEvent::Yank);
+        assert_eq!(le.content_lines().join("\n"), "cat | wc");
+        le.apply_event(EditorEvent::YankPop);
+        assert_eq!(le.content_lines().join("\n"), "catfoo bar\n");
+        le.apply_event(EditorEvent::YankPop);
+        assert_eq!(le.content_lines().join("\n"), "cat | wc");
+
+        // yank-pop only works right after a yank
+        le.apply_event(EditorEvent::GoLeft);
+        le.apply_event(EditorEvent::YankPop);
+        assert_eq!(le.content_lines().join("\n"), "cat | wc");
+    }
+
+    #[test]
+    pub fn test_transpose_chars() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["abc".into()]);
+        le.apply_event(EditorEvent::TransposeChars);
+        assert_eq!(le.content_str(), "acb");
+        assert_eq!(le.cursor_col, 3);
+        le.apply_event(EditorEvent::Home);
+        le.apply_event(EditorEvent::GoRight);
+        le.apply_event(EditorEvent::TransposeChars);
+        assert_eq!(le.content_str(), "cab");
+        assert_eq!(le.cursor_col, 2);
+    }
+
+    #[test]
+    pub fn test_selection_copy_paste() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["cat foo".into(), "| grep bar".into()]);
+        le.set_cursor_offset(4);
+        le.apply_event(EditorEvent::SelectDown);
+        le.apply_event(EditorEvent::SelectLeft);
+        assert_eq!(le.selection(), Some(4..11));
+        le.apply_event(EditorEvent::Copy);
+        assert_eq!(le.take_copied_text(), Some("foo\n| g".to_string()));
+        assert_eq!(le.take_copied_text(), None);
+        assert_eq!(le.selection(), Some(4..11));
+
+        // moving the cursor ends the selection
+        le.apply_event(EditorEvent::End);
+        assert_eq!(le.selection(), None);
+        le.apply_event(EditorEvent::Paste);
+        assert_eq!(le.content_lines().join("\n"), "cat foo\n| grep barfoo\n| g");
+
+        le.apply_event(EditorEvent::SelectHome);
+        le.apply_event(EditorEvent::Cut);
+        assert_eq!(le.content_lines().join("\n"), "cat foo\n| grep barfoo\n");
+        assert_eq!(le.take_copied_text(), Some("| g".to_string()));
+
+        // typing replaces the selection
+        le.apply_event(EditorEvent::GoUp);
+        le.apply_event(EditorEvent::Home);
+        le.apply_event(EditorEvent::SelectEnd);
+        le.apply_event(EditorEvent::NewCharacter('x'));
+        assert_eq!(le.content_lines().join("\n"), "cat foo\nx\n");
+        le.apply_event(EditorEvent::SelectLeft);
+        le.apply_event(EditorEvent::Backspace);
+        assert_eq!(le.content_lines().join("\n"), "cat foo\n\n");
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(le.content_lines().join("\n"), "cat foo\nx\n");
+    }
+
+    #[test]
+    pub fn test_insert_text() {
+        let mut le = EditorState::new();
+        le.set_content(vec!["cat foo".into()]);
+        le.set_cursor_offset(3);
+        le.apply_event(EditorEvent::InsertText(" -n\r\n| wc\r".into()));
+        assert_eq!(*le.content_lines(), vec!["cat -n", "| wc", " foo"]);
+        assert_eq!((le.cursor_line, le.cursor_col), (2, 0));
+        le.apply_event(EditorEvent::Undo);
+        assert_eq!(*le.content_lines(), vec!["cat foo"]);
+    }
 }
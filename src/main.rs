@@ -6,30 +6,41 @@ use std::fs::File;
 use std::io::{self, Read, Write};
 use std::path::Path;
 use std::process::{Command, Stdio};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use tokio::stream::StreamExt;
 use tui::{backend::CrosstermBackend, Terminal};
 
 use crossterm::{
-    event::Event as CEvent,
+    event::{DisableBracketedPaste, EnableBracketedPaste, Event as CEvent},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 mod app;
+mod bookmarks;
 mod command_evaluation;
+mod command_policy;
 mod command_template;
 mod commandlist;
+mod fuzzy;
+mod history_import;
+mod keybindings;
 mod lineeditor;
+mod piped_input;
 mod pipr_config;
+mod resource_limits;
+mod sandbox;
+mod shell_lexer;
 mod snippets;
 mod ui;
 mod util;
+mod vi_mode;
 
 use app::app::App;
 use command_evaluation::*;
 use commandlist::CommandList;
+use piped_input::PipedInputReader;
 use pipr_config::*;
 
 pub struct CliArgs {
@@ -38,6 +49,7 @@ pub struct CliArgs {
     input_file: Option<String>,
     unsafe_mode: bool,
     raw_mode: bool,
+    import_history: Option<String>,
 }
 
 #[tokio::main]
@@ -49,29 +61,72 @@ async fn main() -> Result<(), failure::Error> {
         .unwrap_or(Path::new(&home_path).join(".config"))
         .join("pipr");
 
-    let config = PiprConfig::load_from_file(&config_path.join("pipr.toml"));
+    let config_file_path = config_path.join("pipr.toml");
+    let config = PiprConfig::load_from_file(&config_file_path).unwrap_or_else(|err| {
+        eprintln!("Error in config file {}: {}", config_file_path.display(), err);
+        std::process::exit(1);
+    });
+
+    let mut history = CommandList::load_from_file(config_path.join("history"), Some(config.history_size));
+
+    // this happens before the piped data is read, so the history can be piped in as well (`--import-history /dev/stdin`)
+    if let Some(import_path) = args.import_history {
+        let contents = std::fs::read(&import_path)?;
+        let imported = history_import::import_pipelines(&contents);
+        let found = imported.len();
+        let added = history.import(imported);
+        if let Some(err) = history.io_error {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        println!("Imported {} of {} pipelines from {}", added, found, import_path);
+        return Ok(());
+    }
 
     let execution_mode = if args.unsafe_mode {
-        ExecutionMode::UNSAFE
+        ExecutionMode::UNSAFE(config.command_policy.clone())
     } else {
-        ExecutionMode::ISOLATED
+        // the sandbox is only checked, and its scratch directory created, when it's actually used
+        if let Err(err) = config.sandbox.prepare() {
+            eprintln!("Error in config file {}: invalid [sandbox] section: {}", config_file_path.display(), err);
+            std::process::exit(1);
+        }
+        ExecutionMode::ISOLATED(config.sandbox.clone())
     };
 
     let bubblewrap_available = which::which("bwrap").is_ok();
 
-    if !bubblewrap_available && execution_mode != ExecutionMode::UNSAFE {
+    if !bubblewrap_available && !args.unsafe_mode {
         println!("bubblewrap installation not found. Please make sure you have `bwrap` on your path, or supply --no-isolation to disable safe-mode");
         std::process::exit(1);
     }
 
-    let execution_handler = CommandExecutionHandler::start(config.cmd_timeout, execution_mode, config.eval_environment.clone());
+    let execution_handler = CommandExecutionHandler::start(
+        config.cmd_timeout,
+        config.resource_limits.clone(),
+        execution_mode,
+        config.eval_environment.clone(),
+    );
 
-    let bookmarks = CommandList::load_from_file(config_path.join("bookmarks"), None);
-    let history = CommandList::load_from_file(config_path.join("history"), Some(config.history_size));
+    let bookmarks = CommandList::load_bookmarks(config_path.join("bookmarks.toml"), config_path.join("bookmarks"));
 
     // create app and set default
 
     let mut app = App::new(execution_handler, args.raw_mode, config.clone(), bookmarks, history);
+    // piped data is read in the background, as the producer may never finish.
+    // The tui reads its input from /dev/tty then.
+    if !atty::is(Stream::Stdin) {
+        app.piped_input = Some(Vec::new());
+        app.piped_input_reader = Some(PipedInputReader::start(
+            io::BufReader::new(io::stdin()),
+            config.resource_limits.max_input_bytes,
+        ));
+    }
+    let (bookmark_collections, collection_errors) = bookmarks::load_collections(&config.bookmark_collections);
+    app.bookmark_collections = bookmark_collections;
+    if !collection_errors.is_empty() {
+        app.collection_error = Some(collection_errors.join("; "));
+    }
 
     if let Some(default_value) = args.default_content {
         app.input_state.set_content(default_value.lines().map_into().collect());
@@ -106,6 +161,12 @@ fn handle_cli_arguments() -> CliArgs {
     opts.optopt("o", "out-file", "write final command to file", "FILE");
     opts.optopt("", "in-file", "read initial command from file", "FILE");
     opts.optflag("", "config-reference", "print out the default configuration file");
+    opts.optopt(
+        "",
+        "import-history",
+        "import the pipelines from a bash, zsh or fish history file into pipr's history, then exit",
+        "FILE",
+    );
     opts.optflag("r", "raw-mode", "keep linebreaks in finished command when closing");
     opts.optflag(
         "",
@@ -137,12 +198,15 @@ fn handle_cli_arguments() -> CliArgs {
         input_file: matches.opt_str("in-file"),
         unsafe_mode: matches.opt_present("no-isolation"),
         raw_mode: matches.opt_present("raw-mode"),
+        import_history: matches.opt_str("import-history"),
     }
 }
 
 /// executed after the program has been closed.
 /// optionally given out_file, a path to a file that the
-/// final command will be written to (mostly for scripting stuff)
+/// final command will be written to (mostly for scripting stuff).
+/// If data was piped into pipr, the final command is run on it and its output is printed instead of the command,
+/// so pipr can be used as an interactive filter within a pipeline.
 fn after_finish(app: &App, out_file: Option<String>) -> Result<(), failure::Error> {
     let finished_command = if app.raw_mode {
         app.input_state.content_lines().join("\n")
@@ -160,24 +224,51 @@ fn after_finish(app: &App, out_file: Option<String>) -> Result<(), failure::Erro
         }
     }
 
-    println!("{}", finished_command);
+    if app.piped_input.is_none() {
+        println!("{}", finished_command);
+    }
     if let Some(out_file) = out_file {
         File::create(out_file)?.write_all(finished_command.as_bytes())?;
     }
+
+    if let Some(piped_input) = &app.piped_input {
+        let handler = &app.execution_handler;
+        let command = shell_lexer::strip_comments(&app.input_state.content_lines().join("\n"));
+        let command = if app.raw_mode { command } else { command.lines().join(" ") };
+        // the output is streamed straight through, so nothing gets cut off by max_output_bytes
+        let status = handler.execution_mode.run_cmd_passthrough(
+            &handler.eval_environment,
+            &handler.resource_limits,
+            &command,
+            piped_input,
+            handler.cmd_timeout,
+        );
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                eprintln!("Error running `{}`: {}", finished_command, status);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Error running `{}`: {}", finished_command, err);
+                std::process::exit(1);
+            }
+        }
+    }
     Ok(())
 }
 
 /// TODO: "Pretty descriptive of a name, I argue" - El Kowar 2022 in Discord:TM: vc
 async fn run_app<W: Write>(mut app: &mut App, mut output_stream: W) -> Result<(), failure::Error> {
-    execute!(output_stream, EnterAlternateScreen)?;
+    execute!(output_stream, EnterAlternateScreen, EnableBracketedPaste)?;
     enable_raw_mode()?;
     let backend = CrosstermBackend::new(output_stream);
     let mut terminal = Terminal::new(backend)?;
 
     std::panic::set_hook(Box::new(|data| {
         disable_raw_mode().unwrap();
-        execute!(io::stdout(), LeaveAlternateScreen).unwrap();
-        execute!(io::stderr(), LeaveAlternateScreen).unwrap();
+        execute!(io::stdout(), DisableBracketedPaste, LeaveAlternateScreen).unwrap();
+        execute!(io::stderr(), DisableBracketedPaste, LeaveAlternateScreen).unwrap();
         eprintln!("{}", data);
         std::process::exit(1);
     }));
@@ -195,8 +286,11 @@ async fn run_app<W: Write>(mut app: &mut App, mut output_stream: W) -> Result<()
         tokio::select! {
             Some(cmd_output) = app.execution_handler.cmd_out_receive.recv() => app.on_cmd_output(cmd_output),
             _ = tick_interval.tick() => app.on_tick(),
+            _ = wait_until(app.autoeval_deadline) => app.on_autoeval_deadline().await,
             Some(maybe_event) = crossterm_event_stream.next() => match maybe_event {
                 Ok(CEvent::Key(key_evt)) => app.on_tui_event(key_evt.code, key_evt.modifiers).await,
+                // with bracketed paste enabled, the terminal sends pasted text as a single event
+                Ok(CEvent::Paste(text)) => app.on_paste(text).await,
                 Err(_) => break,
                 _ => {}
             }
@@ -204,10 +298,18 @@ async fn run_app<W: Write>(mut app: &mut App, mut output_stream: W) -> Result<()
     }
     app.execution_handler.stop().await;
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), DisableBracketedPaste, LeaveAlternateScreen)?;
     std::io::Write::flush(&mut terminal.backend_mut())?;
     if !all_errors.is_empty() {
         eprintln!("{}", all_errors.join("\n"));
     }
     Ok(())
 }
+
+/// resolves once the given deadline is reached, or never if there is none
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::delay_until(tokio::time::Instant::from_std(deadline)).await,
+        None => futures::future::pending().await,
+    }
+}
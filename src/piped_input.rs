@@ -0,0 +1,102 @@
+use std::io::{BufRead, Read};
+use std::sync::mpsc::{self, Receiver};
+
+/// a message from the thread reading the piped data
+enum PipedLine {
+    Line(String),
+    /// reading stopped at the size limit
+    Truncated,
+}
+
+/// reads the data piped into pipr on a separate thread,
+/// so a producer that never finishes, like `tail -f`, doesn't keep pipr from starting.
+pub struct PipedInputReader {
+    receiver: Receiver<PipedLine>,
+    /// whether reading stopped at the size limit
+    pub truncated: bool,
+}
+
+impl PipedInputReader {
+    /// start reading the lines of the source, stopping after `max_bytes` (0 means unlimited).
+    /// Bytes that aren't valid UTF-8 are replaced.
+    pub fn start<R: BufRead + Send + 'static>(mut source: R, max_bytes: usize) -> PipedInputReader {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut read_bytes = 0;
+            let mut line = Vec::new();
+            loop {
+                // read at most one byte more than allowed, so a huge line doesn't get buffered completely
+                let remaining = if max_bytes == 0 { u64::MAX } else { (max_bytes - read_bytes) as u64 + 1 };
+                line.clear();
+                match Read::take(&mut source, remaining).read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(count) => read_bytes += count,
+                }
+                if max_bytes != 0 && read_bytes > max_bytes {
+                    sender.send(PipedLine::Truncated).ok();
+                    break;
+                }
+                if line.ends_with(b"\n") {
+                    line.pop();
+                    if line.ends_with(b"\r") {
+                        line.pop();
+                    }
+                }
+                // the reader was dropped
+                if sender.send(PipedLine::Line(String::from_utf8_lossy(&line).into_owned())).is_err() {
+                    break;
+                }
+            }
+        });
+        PipedInputReader {
+            receiver,
+            truncated: false,
+        }
+    }
+
+    /// append the lines read since the last call, returning whether there were any
+    pub fn receive_lines(&mut self, lines: &mut Vec<String>) -> bool {
+        let mut received = false;
+        for piped_line in self.receiver.try_iter() {
+            match piped_line {
+                PipedLine::Line(line) => lines.push(line),
+                PipedLine::Truncated => self.truncated = true,
+            }
+            received = true;
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    /// receive lines until the reader thread is done
+    fn read_all(mut reader: PipedInputReader) -> (Vec<String>, bool) {
+        let mut lines = Vec::new();
+        while let Ok(piped_line) = reader.receiver.recv_timeout(Duration::from_secs(5)) {
+            match piped_line {
+                PipedLine::Line(line) => lines.push(line),
+                PipedLine::Truncated => reader.truncated = true,
+            }
+        }
+        (lines, reader.truncated)
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let reader = PipedInputReader::start(Cursor::new(b"a\r\nb\xff\nc".to_vec()), 0);
+        assert_eq!(read_all(reader), (vec!["a".to_string(), "b\u{fffd}".to_string(), "c".to_string()], false));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let reader = PipedInputReader::start(Cursor::new(b"ab\ncd\nef\n".to_vec()), 7);
+        assert_eq!(read_all(reader), (vec!["ab".to_string(), "cd".to_string()], true));
+        let reader = PipedInputReader::start(Cursor::new(vec![b'x'; 100]), 10);
+        assert_eq!(read_all(reader), (Vec::new(), true));
+    }
+}
@@ -6,7 +6,11 @@ use std::{path::PathBuf, time::Duration};
 use super::snippets::*;
 use maplit::hashmap;
 
+use crate::command_policy::CommandPolicy;
 use crate::command_template::CommandTemplate;
+use crate::keybindings::Keybindings;
+use crate::resource_limits::ResourceLimits;
+use crate::sandbox::{expand_home, SandboxConfig};
 
 pub const DEFAULT_CONFIG: &str = "
 #  ____  _
@@ -27,14 +31,29 @@ paranoid_history_mode_default = false
 
 autoeval_mode_default = true
 
+# In autoeval mode, wait until you stopped typing for this long before running the command.
+# Set to 0 to run the command after every keypress.
+autoeval_debounce_millis = 150
+
 history_size = 500
 cmdlist_always_show_preview = false
 cmd_timeout_millis = 2000
 
 highlighting_enabled = true
 
+# How the command is edited: \"emacs\" for the default keybindings, or \"vi\" for modal vi-style editing.
+editor_mode = \"emacs\"
+
 eval_environment = [\"bash\", \"-c\"]
 
+# Shared, read-only bookmark collections, shown in the bookmark list next to your own bookmarks.
+# Each entry is a file in the format of bookmarks.toml, or a directory containing such files.
+# bookmark_collections = [\"~/team/pipelines.toml\", \"/etc/pipr/collections\"]
+
+# Also put text you copy or cut in the editor into your system clipboard,
+# using the OSC 52 escape sequence. This needs to be supported (and possibly enabled) in your terminal.
+osc52_clipboard = false
+
 # Snippets can be used to quickly insert common bits of shell
 # use || (two pipes) where you want your cursor to be after insertion
 [snippets]
@@ -46,8 +65,79 @@ s = \" | sed -r 's/||//g'\"
 
 [output_viewers]
 'l' = \"less\"
+
+# Limits on the resources of every process your commands start. 0 means unlimited.
+[limits]
+# CPU time in seconds a process may use before it is killed.
+cpu_time_secs = 0
+# Maximum address space of a process in megabytes. Programs like the JVM or Go binaries
+# reserve a lot more address space than they use, and fail to start with a low limit.
+memory_megabytes = 0
+# Maximum number of processes of your user.
+max_processes = 0
+# Output is cut off after this many bytes, and the command is stopped.
+max_output_bytes = 1048576
+# Data piped into pipr is read up to this many bytes.
+max_input_bytes = 16777216
+
+# The bubblewrap sandbox your commands are evaluated in (unless you use --no-isolation).
+# By default, your whole filesystem is mounted read-only, with an empty /tmp.
+[sandbox]
+# Additional mounts, given as \"path\" or \"source:destination\". bind mounts are writable.
+# bind = [\"~/playground\"]
+# ro_bind = [\"~/data:/data\"]
+
+# A directory that is mounted writable, for commands that need to write files.
+# scratch_dir = \"~/.cache/pipr/scratch\"
+
+# Disable network access
+# unshare_net = true
+
+# setenv = { LC_ALL = \"C\" }
+# unsetenv = [\"AWS_SECRET_ACCESS_KEY\"]
+
+# The directory commands are run in. Defaults to the directory pipr was started in.
+# workdir = \"~\"
+
+# Bind keys to actions, on top of the default bindings. Keys are written like \"ctrl+b\", \"alt+enter\" or \"f4\",
+# binding a key to \"none\" removes its default binding. Press F1 to see the current bindings.
+# Actions: toggle_help, toggle_autoeval, toggle_paranoid_history, toggle_history, toggle_bookmarks,
+# open_help_viewer, open_output_viewer, cache_command_part, toggle_stage_inspector, toggle_sample_input,
+# cancel_execution, save_bookmark, execute, quit, autocomplete, history_prev, history_next, history_search, clear_to_history,
+# insert_snippet, new_line, clear, undo, redo, home, end, go_left, go_right, go_up, go_down, go_word_left,
+# go_word_right, backspace, delete, kill_to_line_end, kill_word_forward, kill_word_back, yank, yank_pop,
+# transpose_chars, upcase_word, downcase_word, capitalize_word, select_left, select_right, select_up, select_down,
+# select_home, select_end, copy, cut, paste
+[keybindings]
+# \"alt+h\" = \"toggle_history\"
+# \"f4\" = \"none\"
+
+# The rules deciding which commands may run when sandboxing is disabled with --no-isolation.
+# Every command in your pipeline is checked, including the ones run through xargs, find -exec,
+# bash -c and command substitutions.
+[command_policy]
+# If set, only these commands may be run.
+# allow_commands = [\"cat\", \"grep\", \"sed\", \"awk\", \"sort\", \"uniq\", \"wc\", \"head\", \"tail\"]
+
+# Commands that may never be run. Setting this replaces the default list.
+# deny_commands = [\"rm\", \"rmdir\", \"unlink\", \"mv\", \"cp\", \"dd\", \"shred\", \"truncate\", \"tee\"]
+
+# Redirecting output into files (`> file`) is refused, except for these targets.
+# allow_output_redirection = false
+# allowed_redirection_targets = [\"/dev/null\", \"/dev/stdout\", \"/dev/stderr\"]
+
+# Arguments that are refused for specific commands. Setting this replaces the default list.
+# [command_policy.deny_arguments]
+# find = [\"-delete\"]
+# sed = [\"-i\", \"--in-place\"]
 ";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorMode {
+    Emacs,
+    Vi,
+}
+
 #[derive(Debug, Clone)]
 pub struct PiprConfig {
     pub finish_hook: Option<String>,
@@ -55,27 +145,38 @@ pub struct PiprConfig {
     pub paranoid_history_mode_default: bool,
     pub eval_environment: Vec<String>,
     pub autoeval_mode_default: bool,
+    pub autoeval_debounce: Duration,
     pub cmd_timeout: Duration,
     pub history_size: usize,
+    /// files or directories of read-only bookmarks
+    pub bookmark_collections: Vec<PathBuf>,
     pub snippets: HashMap<char, Snippet>,
     pub help_viewers: HashMap<char, CommandTemplate>,
     pub output_viewers: HashMap<char, String>,
     pub highlighting_enabled: bool,
+    pub osc52_clipboard: bool,
+    pub editor_mode: EditorMode,
+    pub keybindings: Keybindings,
+    pub sandbox: SandboxConfig,
+    pub command_policy: CommandPolicy,
+    pub resource_limits: ResourceLimits,
 }
 
 impl PiprConfig {
-    pub fn load_from_file(path: &PathBuf) -> PiprConfig {
+    /// load the config file, creating it with the default configuration if it doesn't exist.
+    /// returns a description of the problem if the configuration is invalid.
+    pub fn load_from_file(path: &PathBuf) -> Result<PiprConfig, String> {
         DirBuilder::new().recursive(true).create(&path.parent().unwrap()).unwrap();
         if !path.exists() {
             create_default_file(&path);
         }
         let mut settings = config::Config::default();
         let config_file = config::File::new(path.to_str().unwrap(), config::FileFormat::Toml);
-        settings.merge(config_file).unwrap();
+        settings.merge(config_file).map_err(|err| format!("{}", err))?;
         PiprConfig::from_settings(settings)
     }
 
-    fn from_settings(settings: config::Config) -> PiprConfig {
+    fn from_settings(settings: config::Config) -> Result<PiprConfig, String> {
         let snippets = settings
             .get::<HashMap<char, String>>("snippets")
             .unwrap_or_default()
@@ -93,10 +194,49 @@ impl PiprConfig {
             .map(|(k, v)| (k, CommandTemplate::from_string(v).unwrap()))
             .collect::<HashMap<_, _>>();
 
-        PiprConfig {
+        let sandbox = match settings.get::<SandboxConfig>("sandbox") {
+            Ok(sandbox) => sandbox,
+            Err(config::ConfigError::NotFound(_)) => SandboxConfig::default(),
+            Err(err) => return Err(format!("invalid [sandbox] section: {}", err)),
+        };
+
+        let command_policy = match settings.get::<CommandPolicy>("command_policy") {
+            Ok(command_policy) => command_policy,
+            Err(config::ConfigError::NotFound(_)) => CommandPolicy::default(),
+            Err(err) => return Err(format!("invalid [command_policy] section: {}", err)),
+        };
+
+        let resource_limits = match settings.get::<ResourceLimits>("limits") {
+            Ok(resource_limits) => resource_limits,
+            Err(config::ConfigError::NotFound(_)) => ResourceLimits::default(),
+            Err(err) => return Err(format!("invalid [limits] section: {}", err)),
+        };
+
+        let editor_mode = match settings.get::<String>("editor_mode").as_deref() {
+            Ok("emacs") | Err(config::ConfigError::NotFound(_)) => EditorMode::Emacs,
+            Ok("vi") => EditorMode::Vi,
+            Ok(other) => return Err(format!("invalid editor_mode `{}`, expected \"emacs\" or \"vi\"", other)),
+            Err(err) => return Err(format!("invalid editor_mode: {}", err)),
+        };
+
+        let keybindings = match settings.get::<HashMap<String, String>>("keybindings") {
+            Ok(overrides) => Keybindings::with_overrides(&overrides),
+            Err(config::ConfigError::NotFound(_)) => Ok(Keybindings::default()),
+            Err(err) => Err(err.to_string()),
+        }
+        .map_err(|err| format!("invalid [keybindings] section: {}", err))?;
+
+        let bookmark_collections = match settings.get::<Vec<String>>("bookmark_collections") {
+            Ok(paths) => paths.iter().map(|path| expand_home(path)).collect(),
+            Err(config::ConfigError::NotFound(_)) => Vec::new(),
+            Err(err) => return Err(format!("invalid bookmark_collections: {}", err)),
+        };
+
+        Ok(PiprConfig {
             finish_hook: settings.get::<String>("finish_hook").ok(),
             paranoid_history_mode_default: settings.get::<bool>("paranoid_history_mode_default").unwrap_or(false),
             autoeval_mode_default: settings.get::<bool>("autoeval_mode_default").unwrap_or(false),
+            autoeval_debounce: Duration::from_millis(settings.get::<u64>("autoeval_debounce_millis").unwrap_or(150)),
             cmd_timeout: Duration::from_millis(settings.get::<u64>("cmd_timeout_millis").unwrap_or(2000)),
             eval_environment: settings
                 .get::<Vec<String>>("eval_environment")
@@ -104,12 +244,19 @@ impl PiprConfig {
             history_size: settings.get::<usize>("history_size").unwrap_or(500),
             cmdlist_always_show_preview: settings.get::<bool>("cmdlist_always_show_preview").unwrap_or(false),
             highlighting_enabled: settings.get::<bool>("highlighting_enabled").unwrap_or(true),
+            osc52_clipboard: settings.get::<bool>("osc52_clipboard").unwrap_or(false),
             output_viewers: settings
                 .get::<HashMap<char, String>>("output_viewers")
                 .unwrap_or_else(|_| hashmap! { 'l' => "less".into() }),
+            bookmark_collections,
             help_viewers,
             snippets,
-        }
+            sandbox,
+            command_policy,
+            resource_limits,
+            editor_mode,
+            keybindings,
+        })
     }
 }
 
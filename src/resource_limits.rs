@@ -0,0 +1,139 @@
+use serde::Deserialize;
+
+/// the line appended to output that was cut off at `max_output_bytes`
+pub const OUTPUT_TRUNCATED_MARKER: &str = "[pipr: output truncated]";
+
+/// Limits on the resources evaluated commands may use,
+/// read from the `[limits]` section of the config file.
+/// A limit of 0 means unlimited.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    /// cpu time in seconds every process may use before it is killed
+    pub cpu_time_secs: u64,
+    /// maximum size of the address space of every process, in megabytes.
+    /// Off by default, as programs like the JVM or Go binaries reserve far more address space than they use.
+    pub memory_megabytes: u64,
+    /// maximum number of processes your user may have while the command is running
+    pub max_processes: u64,
+    /// the captured stdout and stderr are cut off after this many bytes
+    pub max_output_bytes: usize,
+    /// data piped into pipr is read up to this many bytes
+    pub max_input_bytes: usize,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        ResourceLimits {
+            cpu_time_secs: 0,
+            memory_megabytes: 0,
+            max_processes: 0,
+            max_output_bytes: 1024 * 1024,
+            max_input_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// apply the limits to the current process.
+    /// This is called between fork and exec, so it may only use async-signal-safe functions.
+    /// Limits above the current hard limit are ignored, as they can't be raised.
+    pub fn apply(&self) -> std::io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu_time_secs),
+            (libc::RLIMIT_AS, self.memory_megabytes.saturating_mul(1024 * 1024)),
+            (libc::RLIMIT_NPROC, self.max_processes),
+        ];
+        for &(resource, value) in limits.iter() {
+            if value == 0 {
+                continue;
+            }
+            let mut current = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+            unsafe {
+                if libc::getrlimit(resource, &mut current) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let value = value as libc::rlim_t;
+                if current.rlim_max != libc::RLIM_INFINITY && current.rlim_max < value {
+                    continue;
+                }
+                let limit = libc::rlimit { rlim_cur: value, rlim_max: value };
+                if libc::setrlimit(resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// append a line of output to the buffer, cutting it off at `max_output_bytes`.
+    /// Returns false if the line did not fit, in which case the truncation marker is appended
+    /// the first time, and nothing afterwards.
+    pub fn push_line(&self, buffer: &mut String, line: &str) -> bool {
+        let max = self.max_output_bytes;
+        if max == 0 || buffer.len() + line.len() < max {
+            buffer.push_str(line);
+            buffer.push('\n');
+            return true;
+        }
+        if buffer.len() <= max {
+            let mut end = max - buffer.len();
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end > 0 {
+                buffer.push_str(&line[..end]);
+                buffer.push('\n');
+            }
+            buffer.push_str(OUTPUT_TRUNCATED_MARKER);
+            buffer.push('\n');
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_line() {
+        let limits = ResourceLimits {
+            max_output_bytes: 10,
+            ..ResourceLimits::default()
+        };
+        let mut buffer = String::new();
+        assert!(limits.push_line(&mut buffer, "abc"));
+        assert!(limits.push_line(&mut buffer, "def"));
+        assert!(!limits.push_line(&mut buffer, "ghijk"));
+        assert_eq!(buffer, format!("abc\ndef\ngh\n{}\n", OUTPUT_TRUNCATED_MARKER));
+        assert!(!limits.push_line(&mut buffer, "more"));
+        assert_eq!(buffer, format!("abc\ndef\ngh\n{}\n", OUTPUT_TRUNCATED_MARKER));
+    }
+
+    #[test]
+    fn test_push_line_char_boundary() {
+        let limits = ResourceLimits {
+            max_output_bytes: 2,
+            ..ResourceLimits::default()
+        };
+        let mut buffer = String::new();
+        assert!(!limits.push_line(&mut buffer, "äö"));
+        assert_eq!(buffer, format!("ä\n{}\n", OUTPUT_TRUNCATED_MARKER));
+
+        let mut buffer = String::from("a\n");
+        assert!(!limits.push_line(&mut buffer, "b"));
+        assert_eq!(buffer, format!("a\n{}\n", OUTPUT_TRUNCATED_MARKER));
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limits = ResourceLimits {
+            max_output_bytes: 0,
+            ..ResourceLimits::default()
+        };
+        let mut buffer = String::new();
+        assert!(limits.push_line(&mut buffer, &"x".repeat(10_000)));
+        assert_eq!(buffer.len(), 10_001);
+    }
+}
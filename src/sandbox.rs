@@ -0,0 +1,215 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// bubblewrap arguments that are always used: a read-only root with a private /tmp, /dev and /proc
+const BASE_BUBBLEWRAP_ARGS: &[&str] = &[
+    "--ro-bind",
+    "/",
+    "/",
+    "--tmpfs",
+    "/tmp",
+    "--dev",
+    "/dev",
+    "--proc",
+    "/proc",
+    "--die-with-parent",
+    "--unshare-pid",
+];
+
+/// Configuration of the bubblewrap sandbox commands are evaluated in,
+/// read from the `[sandbox]` section of the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SandboxConfig {
+    /// paths that are mounted writable, given as `path` or `source:destination`
+    pub bind: Vec<String>,
+    /// paths that are mounted read-only, given as `path` or `source:destination`
+    pub ro_bind: Vec<String>,
+    /// a directory that is mounted writable at the same path, created if it doesn't exist
+    pub scratch_dir: Option<String>,
+    /// disable network access
+    pub unshare_net: bool,
+    /// environment variables that are set within the sandbox
+    pub setenv: BTreeMap<String, String>,
+    /// environment variables that are removed within the sandbox
+    pub unsetenv: Vec<String>,
+    /// the directory commands are run in. Defaults to the directory pipr was started in.
+    pub workdir: Option<String>,
+}
+
+/// a single mount of a path from the host into the sandbox
+#[derive(Debug, PartialEq, Eq)]
+struct Mount {
+    source: PathBuf,
+    destination: PathBuf,
+}
+
+impl Mount {
+    fn parse(spec: &str) -> Result<Mount, String> {
+        let (source, destination) = match spec.find(':') {
+            Some(idx) => (&spec[..idx], &spec[idx + 1..]),
+            None => (spec, spec),
+        };
+        if source.is_empty() || destination.is_empty() {
+            return Err(format!("invalid mount `{}`, expected `path` or `source:destination`", spec));
+        }
+        let mount = Mount {
+            source: expand_home(source),
+            destination: expand_home(destination),
+        };
+        if !mount.destination.is_absolute() {
+            return Err(format!("mount destination `{}` must be an absolute path", mount.destination.display()));
+        }
+        if !mount.source.exists() {
+            return Err(format!("mount source `{}` does not exist", mount.source.display()));
+        }
+        Ok(mount)
+    }
+}
+
+impl SandboxConfig {
+    /// check that the configuration can be used, creating the scratch directory if necessary.
+    /// Returns a description of the first problem found.
+    pub fn prepare(&self) -> Result<(), String> {
+        for spec in self.bind.iter().chain(self.ro_bind.iter()) {
+            Mount::parse(spec)?;
+        }
+        if let Some(scratch_dir) = &self.scratch_dir {
+            let scratch_dir = expand_home(scratch_dir);
+            if !scratch_dir.is_absolute() {
+                return Err(format!("scratch_dir `{}` must be an absolute path", scratch_dir.display()));
+            }
+            std::fs::create_dir_all(&scratch_dir)
+                .map_err(|err| format!("could not create scratch_dir `{}`: {}", scratch_dir.display(), err))?;
+        }
+        if let Some(workdir) = &self.workdir {
+            if !expand_home(workdir).is_dir() {
+                return Err(format!("workdir `{}` is not a directory", workdir));
+            }
+        }
+        if let Some(name) = self.setenv.keys().chain(self.unsetenv.iter()).find(|name| name.is_empty() || name.contains('=')) {
+            return Err(format!("invalid environment variable name `{}`", name));
+        }
+        Ok(())
+    }
+
+    /// the arguments passed to bwrap, in front of the command that should be run.
+    /// Expects the configuration to be valid, mounts that can't be parsed are skipped.
+    pub fn bwrap_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = BASE_BUBBLEWRAP_ARGS.iter().map(|arg| arg.to_string()).collect();
+        args.push(if self.unshare_net { "--unshare-net" } else { "--share-net" }.to_string());
+
+        let mounts = self
+            .ro_bind
+            .iter()
+            .map(|spec| ("--ro-bind", spec))
+            .chain(self.bind.iter().map(|spec| ("--bind", spec)));
+        for (flag, spec) in mounts {
+            if let Ok(mount) = Mount::parse(spec) {
+                args.push(flag.to_string());
+                args.push(mount.source.display().to_string());
+                args.push(mount.destination.display().to_string());
+            }
+        }
+        if let Some(scratch_dir) = &self.scratch_dir {
+            let scratch_dir = expand_home(scratch_dir).display().to_string();
+            args.extend(vec!["--bind".to_string(), scratch_dir.clone(), scratch_dir]);
+        }
+        for (name, value) in &self.setenv {
+            args.extend(vec!["--setenv".to_string(), name.clone(), value.clone()]);
+        }
+        for name in &self.unsetenv {
+            args.extend(vec!["--unsetenv".to_string(), name.clone()]);
+        }
+        if let Some(workdir) = &self.workdir {
+            args.extend(vec!["--chdir".to_string(), expand_home(workdir).display().to_string()]);
+        }
+        args
+    }
+}
+
+/// replace a leading `~` with the users home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            Path::new(&home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_args() {
+        let args = SandboxConfig::default().bwrap_args();
+        assert_eq!(
+            args.join(" "),
+            "--ro-bind / / --tmpfs /tmp --dev /dev --proc /proc --die-with-parent --unshare-pid --share-net"
+        );
+    }
+
+    #[test]
+    fn test_configured_args() {
+        let config = SandboxConfig {
+            bind: vec!["/tmp:/mnt/tmp".into()],
+            ro_bind: vec!["/".into(), "/does/not/exist".into()],
+            unshare_net: true,
+            setenv: vec![("FOO".to_string(), "bar baz".to_string())].into_iter().collect(),
+            unsetenv: vec!["SECRET".into()],
+            workdir: Some("/tmp".into()),
+            ..SandboxConfig::default()
+        };
+        let args = config.bwrap_args();
+        let args = &args[BASE_BUBBLEWRAP_ARGS.len()..];
+        assert_eq!(
+            args,
+            &[
+                "--unshare-net",
+                "--ro-bind",
+                "/",
+                "/",
+                "--bind",
+                "/tmp",
+                "/mnt/tmp",
+                "--setenv",
+                "FOO",
+                "bar baz",
+                "--unsetenv",
+                "SECRET",
+                "--chdir",
+                "/tmp"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let valid = SandboxConfig {
+            bind: vec!["/tmp".into()],
+            ..SandboxConfig::default()
+        };
+        assert_eq!(valid.prepare(), Ok(()));
+
+        let missing_source = SandboxConfig {
+            ro_bind: vec!["/does/not/exist".into()],
+            ..SandboxConfig::default()
+        };
+        assert!(missing_source.prepare().unwrap_err().contains("does not exist"));
+
+        let relative_destination = SandboxConfig {
+            bind: vec!["/tmp:tmp".into()],
+            ..SandboxConfig::default()
+        };
+        assert!(relative_destination.prepare().unwrap_err().contains("absolute"));
+
+        let invalid_env = SandboxConfig {
+            unsetenv: vec!["A=B".into()],
+            ..SandboxConfig::default()
+        };
+        assert!(invalid_env.prepare().is_err());
+    }
+}
@@ -0,0 +1,539 @@
+use std::ops::Range;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Word,
+    /// `|`
+    Pipe,
+    /// `|&`, piping both stdout and stderr
+    PipeAll,
+    /// `||`
+    Or,
+    /// `&&`
+    And,
+    /// `;` or `;;`
+    Semicolon,
+    /// `&`
+    Background,
+    /// `(`, starting a subshell
+    OpenParen,
+    /// `)`, ending a subshell
+    CloseParen,
+    /// a redirection operator like `>`, `2>>`, `&>`, `<` or `<<<`, including its file-descriptor prefix
+    Redirect,
+    /// `<<` or `<<-`, the delimiter is the following word
+    Heredoc,
+    /// the lines of a heredoc, including the line containing the closing delimiter
+    HeredocBody,
+    Newline,
+    /// a comment, starting at the `#` and ending before the end of the line
+    Comment,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// byte range of this token in the input
+    pub span: Range<usize>,
+}
+
+/// a single stage of a pipeline, i.e. everything between two top-level pipe symbols
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PipelineStage {
+    /// byte range of this stage in the input, without surrounding whitespace
+    pub span: Range<usize>,
+    /// byte offset of the pipe symbol that connects this stage to the previous one
+    pub pipe_offset: Option<usize>,
+}
+
+const OPERATORS: &[(&str, TokenKind)] = &[
+    ("&>>", TokenKind::Redirect),
+    ("<<<", TokenKind::Redirect),
+    ("<<-", TokenKind::Heredoc),
+    ("||", TokenKind::Or),
+    ("|&", TokenKind::PipeAll),
+    ("&&", TokenKind::And),
+    (";;", TokenKind::Semicolon),
+    ("&>", TokenKind::Redirect),
+    (">>", TokenKind::Redirect),
+    (">|", TokenKind::Redirect),
+    (">&", TokenKind::Redirect),
+    ("<&", TokenKind::Redirect),
+    ("<>", TokenKind::Redirect),
+    ("<<", TokenKind::Heredoc),
+    ("|", TokenKind::Pipe),
+    ("&", TokenKind::Background),
+    (";", TokenKind::Semicolon),
+    ("(", TokenKind::OpenParen),
+    (")", TokenKind::CloseParen),
+    ("<", TokenKind::Redirect),
+    (">", TokenKind::Redirect),
+];
+
+struct PendingHeredoc {
+    delimiter: String,
+    strip_tabs: bool,
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    tokens: Vec<Token>,
+    /// heredocs whose body starts after the next newline
+    pending_heredocs: Vec<PendingHeredoc>,
+    /// set after a heredoc operator, containing whether the body should have leading tabs stripped
+    awaiting_heredoc_delimiter: Option<bool>,
+}
+
+/// split the given shell code into tokens.
+/// This never fails: unterminated quotes or substitutions simply extend to the end of the input.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(input);
+    lexer.run();
+    lexer.tokens
+}
+
+/// split the given shell code into the stages of its pipeline, separated by `|` or `|&`.
+/// Pipes inside quotes, subshells, command substitutions or groups don't separate stages.
+pub fn pipeline_stages(input: &str) -> Vec<PipelineStage> {
+    let mut stages = Vec::new();
+    let mut depth = 0usize;
+    let mut stage_start = 0;
+    let mut pipe_offset = None;
+    for token in tokenize(input) {
+        match token.kind {
+            TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseParen => depth = depth.saturating_sub(1),
+            TokenKind::Word if &input[token.span.clone()] == "{" => depth += 1,
+            TokenKind::Word if &input[token.span.clone()] == "}" => depth = depth.saturating_sub(1),
+            TokenKind::Pipe | TokenKind::PipeAll if depth == 0 => {
+                stages.push(PipelineStage {
+                    span: trimmed_span(input, stage_start..token.span.start),
+                    pipe_offset,
+                });
+                pipe_offset = Some(token.span.start);
+                stage_start = token.span.end;
+            }
+            _ => {}
+        }
+    }
+    let last_span = trimmed_span(input, stage_start..input.len());
+    if !last_span.is_empty() || pipe_offset.is_some() {
+        stages.push(PipelineStage {
+            span: last_span,
+            pipe_offset,
+        });
+    }
+    stages
+}
+
+/// remove all comments from the given shell code, keeping the line structure intact.
+pub fn strip_comments(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    let mut last_end = 0;
+    for token in tokenize(input).into_iter().filter(|token| token.kind == TokenKind::Comment) {
+        result.push_str(&input[last_end..token.span.start]);
+        last_end = token.span.end;
+    }
+    result.push_str(&input[last_end..]);
+    result
+}
+
+fn trimmed_span(input: &str, span: Range<usize>) -> Range<usize> {
+    let text = &input[span.clone()];
+    let start = span.start + (text.len() - text.trim_start().len());
+    let end = span.end - (text.len() - text.trim_end().len());
+    start..end.max(start)
+}
+
+/// find the commands within the command substitutions (`$(...)` or backticks)
+/// and process substitutions (`<(...)`, `>(...)`) of a word.
+/// Returns the byte ranges of the inner commands.
+pub fn command_substitutions(word: &str) -> Vec<Range<usize>> {
+    let lexer = Lexer::new(word);
+    let bytes = word.as_bytes();
+    let mut substitutions = Vec::new();
+    let mut in_double_quotes = false;
+    let mut pos = 0;
+    while let Some(&c) = bytes.get(pos) {
+        match c {
+            b'\\' => pos += 2,
+            b'\'' if !in_double_quotes => pos = lexer.skip_single_quoted(pos),
+            b'"' => {
+                in_double_quotes = !in_double_quotes;
+                pos += 1;
+            }
+            b'`' => {
+                let end = lexer.skip_backticks(pos);
+                let inner_end = if bytes.get(end - 1) == Some(&b'`') && end - 1 > pos { end - 1 } else { end };
+                substitutions.push(pos + 1..inner_end);
+                pos = end;
+            }
+            // arithmetic expansion, which can't contain commands that aren't also in a nested substitution
+            b'$' if word[pos..].starts_with("$((") => pos += 3,
+            b'$' | b'<' | b'>' if bytes.get(pos + 1) == Some(&b'(') => {
+                let end = lexer.skip_parens(pos + 1);
+                let inner_end = if bytes.get(end - 1) == Some(&b')') { end - 1 } else { end };
+                substitutions.push(pos + 2..inner_end.max(pos + 2));
+                pos = end;
+            }
+            _ => pos += 1,
+        }
+    }
+    substitutions
+}
+
+/// remove quotes and backslash-escapes from a word, without expanding anything
+pub fn unquote(word: &str) -> String {
+    let mut result = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (Some('\''), c) => result.push(c),
+            (_, '\\') => result.extend(chars.next()),
+            (_, c) => result.push(c),
+        }
+    }
+    result
+}
+
+impl<'a> Lexer<'a> {
+    fn new(input: &'a str) -> Lexer<'a> {
+        Lexer {
+            input,
+            bytes: input.as_bytes(),
+            pos: 0,
+            tokens: Vec::new(),
+            pending_heredocs: Vec::new(),
+            awaiting_heredoc_delimiter: None,
+        }
+    }
+
+    fn run(&mut self) {
+        while let Some(&c) = self.bytes.get(self.pos) {
+            match c {
+                b' ' | b'\t' | b'\r' => self.pos += 1,
+                b'\\' if self.peek(1) == Some(b'\n') => self.pos += 2,
+                b'\n' => {
+                    self.push(TokenKind::Newline, self.pos..self.pos + 1);
+                    self.pos += 1;
+                    self.read_heredoc_bodies();
+                }
+                b'#' => {
+                    let end = self.line_end(self.pos);
+                    self.push(TokenKind::Comment, self.pos..end);
+                    self.pos = end;
+                }
+                b'<' | b'>' if self.peek(1) == Some(b'(') => self.read_word(),
+                b'|' | b'&' | b';' | b'(' | b')' | b'<' | b'>' => self.read_operator(self.pos),
+                _ => self.read_word(),
+            }
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.pos + offset).copied()
+    }
+
+    fn push(&mut self, kind: TokenKind, span: Range<usize>) {
+        self.tokens.push(Token { kind, span });
+    }
+
+    fn line_end(&self, from: usize) -> usize {
+        self.input[from..].find('\n').map(|idx| from + idx).unwrap_or(self.input.len())
+    }
+
+    /// read the operator at the current position. `start` may be before the current position
+    /// if the operator is prefixed by a file-descriptor number, like in `2>`.
+    fn read_operator(&mut self, start: usize) {
+        let rest = &self.input[self.pos..];
+        let (operator, kind) = OPERATORS
+            .iter()
+            .find(|(operator, _)| rest.starts_with(operator))
+            .expect("read_operator called on a non-operator character");
+        self.pos += operator.len();
+        self.push(*kind, start..self.pos);
+        if *kind == TokenKind::Heredoc {
+            self.awaiting_heredoc_delimiter = Some(*operator == "<<-");
+        }
+    }
+
+    fn read_word(&mut self) {
+        let start = self.pos;
+        while let Some(&c) = self.bytes.get(self.pos) {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' | b'|' | b'&' | b';' | b'(' | b')' => break,
+                b'<' | b'>' if self.peek(1) == Some(b'(') => self.pos = self.skip_parens(self.pos + 1),
+                b'<' | b'>' => break,
+                b'\\' => self.pos += 2,
+                b'\'' => self.pos = self.skip_single_quoted(self.pos),
+                b'"' => self.pos = self.skip_double_quoted(self.pos),
+                b'`' => self.pos = self.skip_backticks(self.pos),
+                b'$' if self.peek(1) == Some(b'(') => self.pos = self.skip_parens(self.pos + 1),
+                b'$' if self.peek(1) == Some(b'{') => self.pos = self.skip_braces(self.pos + 1),
+                b'$' if self.peek(1) == Some(b'\'') => self.pos = self.skip_ansi_c_quoted(self.pos + 1),
+                _ => self.pos += 1,
+            }
+        }
+        self.pos = self.pos.min(self.input.len());
+
+        let word = &self.input[start..self.pos];
+        let is_fd_prefix = !word.is_empty() && word.bytes().all(|c| c.is_ascii_digit());
+        if is_fd_prefix && matches!(self.peek(0), Some(b'<') | Some(b'>')) {
+            self.read_operator(start);
+            return;
+        }
+
+        if let Some(strip_tabs) = self.awaiting_heredoc_delimiter.take() {
+            self.pending_heredocs.push(PendingHeredoc {
+                delimiter: unquote(word),
+                strip_tabs,
+            });
+        }
+        self.push(TokenKind::Word, start..self.pos);
+    }
+
+    fn read_heredoc_bodies(&mut self) {
+        let heredocs = std::mem::take(&mut self.pending_heredocs);
+        for (idx, heredoc) in heredocs.iter().enumerate() {
+            // the body of every heredoc but the first starts after the closing delimiter of the previous one
+            if idx > 0 && self.bytes.get(self.pos) == Some(&b'\n') {
+                self.pos += 1;
+            }
+            let body_start = self.pos;
+            let mut line_start = self.pos;
+            loop {
+                let line_end = self.line_end(line_start);
+                let line = &self.input[line_start..line_end];
+                let line = if heredoc.strip_tabs { line.trim_start_matches('\t') } else { line };
+                if line == heredoc.delimiter || line_end == self.input.len() {
+                    self.pos = line_end;
+                    break;
+                }
+                line_start = line_end + 1;
+            }
+            self.push(TokenKind::HeredocBody, body_start..self.pos);
+        }
+    }
+
+    /// returns the position after the single-quoted string starting at `start`
+    fn skip_single_quoted(&self, start: usize) -> usize {
+        match self.input[start + 1..].find('\'') {
+            Some(idx) => start + 1 + idx + 1,
+            None => self.input.len(),
+        }
+    }
+
+    /// returns the position after the `$'...'` string whose quote starts at `start`
+    fn skip_ansi_c_quoted(&self, start: usize) -> usize {
+        let mut pos = start + 1;
+        while let Some(&c) = self.bytes.get(pos) {
+            match c {
+                b'\\' => pos += 2,
+                b'\'' => return pos + 1,
+                _ => pos += 1,
+            }
+        }
+        self.input.len()
+    }
+
+    /// returns the position after the double-quoted string starting at `start`
+    fn skip_double_quoted(&self, start: usize) -> usize {
+        let mut pos = start + 1;
+        while let Some(&c) = self.bytes.get(pos) {
+            match c {
+                b'\\' => pos += 2,
+                b'"' => return pos + 1,
+                b'`' => pos = self.skip_backticks(pos),
+                b'$' if self.bytes.get(pos + 1) == Some(&b'(') => pos = self.skip_parens(pos + 1),
+                b'$' if self.bytes.get(pos + 1) == Some(&b'{') => pos = self.skip_braces(pos + 1),
+                _ => pos += 1,
+            }
+        }
+        self.input.len()
+    }
+
+    /// returns the position after the backtick-substitution starting at `start`
+    fn skip_backticks(&self, start: usize) -> usize {
+        let mut pos = start + 1;
+        while let Some(&c) = self.bytes.get(pos) {
+            match c {
+                b'\\' => pos += 2,
+                b'`' => return pos + 1,
+                _ => pos += 1,
+            }
+        }
+        self.input.len()
+    }
+
+    /// returns the position after the parenthesized block starting at `start`, which may contain nested blocks
+    fn skip_parens(&self, start: usize) -> usize {
+        self.skip_nested(start, b'(', b')')
+    }
+
+    /// returns the position after the braced block starting at `start`, which may contain nested blocks
+    fn skip_braces(&self, start: usize) -> usize {
+        self.skip_nested(start, b'{', b'}')
+    }
+
+    fn skip_nested(&self, start: usize, open: u8, close: u8) -> usize {
+        let mut depth = 0;
+        let mut pos = start;
+        while let Some(&c) = self.bytes.get(pos) {
+            match c {
+                b'\\' => pos += 2,
+                b'\'' => pos = self.skip_single_quoted(pos),
+                b'"' => pos = self.skip_double_quoted(pos),
+                b'`' => pos = self.skip_backticks(pos),
+                c if c == open => {
+                    depth += 1;
+                    pos += 1;
+                }
+                c if c == close => {
+                    depth -= 1;
+                    pos += 1;
+                    if depth == 0 {
+                        return pos;
+                    }
+                }
+                _ => pos += 1,
+            }
+        }
+        self.input.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token_texts(input: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(input)
+            .into_iter()
+            .map(|token| (token.kind, &input[token.span]))
+            .collect()
+    }
+
+    fn stage_texts(input: &str) -> Vec<&str> {
+        pipeline_stages(input).into_iter().map(|stage| &input[stage.span]).collect()
+    }
+
+    #[test]
+    fn test_simple_pipeline() {
+        use TokenKind::*;
+        assert_eq!(
+            token_texts("cat foo | grep -v bar"),
+            vec![(Word, "cat"), (Word, "foo"), (Pipe, "|"), (Word, "grep"), (Word, "-v"), (Word, "bar")]
+        );
+        assert_eq!(stage_texts("cat foo | grep -v bar"), vec!["cat foo", "grep -v bar"]);
+        assert_eq!(stage_texts("cat foo"), vec!["cat foo"]);
+        assert_eq!(stage_texts(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(stage_texts("echo 'a | b' | grep \"c | d\""), vec!["echo 'a | b'", "grep \"c | d\""]);
+        assert_eq!(stage_texts("echo a\\|b | wc"), vec!["echo a\\|b", "wc"]);
+        assert_eq!(stage_texts("echo \"a \\\" | b\" | wc"), vec!["echo \"a \\\" | b\"", "wc"]);
+        assert_eq!(stage_texts("echo $'a \\' | b' | wc"), vec!["echo $'a \\' | b'", "wc"]);
+    }
+
+    #[test]
+    fn test_operators() {
+        use TokenKind::*;
+        assert_eq!(
+            token_texts("a || b |& c && d 2>&1 >>f &"),
+            vec![
+                (Word, "a"),
+                (Or, "||"),
+                (Word, "b"),
+                (PipeAll, "|&"),
+                (Word, "c"),
+                (And, "&&"),
+                (Word, "d"),
+                (Redirect, "2>&"),
+                (Word, "1"),
+                (Redirect, ">>"),
+                (Word, "f"),
+                (Background, "&"),
+            ]
+        );
+        assert_eq!(stage_texts("a || b |& c"), vec!["a || b", "c"]);
+    }
+
+    #[test]
+    fn test_subshells_and_substitutions() {
+        assert_eq!(stage_texts("(a | b) | c"), vec!["(a | b)", "c"]);
+        assert_eq!(stage_texts("echo $(a | b) | c"), vec!["echo $(a | b)", "c"]);
+        assert_eq!(stage_texts("echo $(echo \")\" | b) | c"), vec!["echo $(echo \")\" | b)", "c"]);
+        assert_eq!(stage_texts("echo `a | b` | c"), vec!["echo `a | b`", "c"]);
+        assert_eq!(stage_texts("diff <(a | b) <(c) | d"), vec!["diff <(a | b) <(c)", "d"]);
+        assert_eq!(stage_texts("{ a | b; } | c"), vec!["{ a | b; }", "c"]);
+        assert_eq!(stage_texts("echo ${a:-|} | c"), vec!["echo ${a:-|}", "c"]);
+    }
+
+    #[test]
+    fn test_comments() {
+        use TokenKind::*;
+        assert_eq!(
+            token_texts("a # b | c\n| d#e"),
+            vec![(Word, "a"), (Comment, "# b | c"), (Newline, "\n"), (Pipe, "|"), (Word, "d#e")]
+        );
+        assert_eq!(strip_comments("a # b | c\n# x\n| d '#'"), "a \n\n| d '#'");
+    }
+
+    #[test]
+    fn test_heredoc() {
+        use TokenKind::*;
+        let input = "cat <<'EOF' | wc\na | b\nEOF\n| sort";
+        assert_eq!(
+            token_texts(input),
+            vec![
+                (Word, "cat"),
+                (Heredoc, "<<"),
+                (Word, "'EOF'"),
+                (Pipe, "|"),
+                (Word, "wc"),
+                (Newline, "\n"),
+                (HeredocBody, "a | b\nEOF"),
+                (Newline, "\n"),
+                (Pipe, "|"),
+                (Word, "sort"),
+            ]
+        );
+        assert_eq!(stage_texts(input), vec!["cat <<'EOF'", "wc\na | b\nEOF", "sort"]);
+        assert_eq!(stage_texts("cat <<-X\n\t# no comment\n\tX"), vec!["cat <<-X\n\t# no comment\n\tX"]);
+    }
+
+    #[test]
+    fn test_command_substitutions() {
+        let substitutions = |word: &'static str| {
+            command_substitutions(word)
+                .into_iter()
+                .map(|span| &word[span])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(substitutions("a$(b c)d`e`"), vec!["b c", "e"]);
+        assert_eq!(substitutions("\"$(a \")\")\""), vec!["a \")\""]);
+        assert_eq!(substitutions("'$(a)'"), Vec::<&str>::new());
+        assert_eq!(substitutions("<(a $(b))"), vec!["a $(b)"]);
+        assert_eq!(substitutions("$((1 + $(a)))"), vec!["a"]);
+        assert_eq!(substitutions("$(a"), vec!["a"]);
+    }
+
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote("'a b'\"c\\\"\"d\\ e"), "a bc\"d e");
+        assert_eq!(unquote("'a\\b'"), "a\\b");
+    }
+
+    #[test]
+    fn test_pipe_offsets() {
+        let offsets = pipeline_stages("a | b |& c").iter().map(|stage| stage.pipe_offset).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![None, Some(2), Some(6)]);
+    }
+}
@@ -1,5 +1,12 @@
-use crate::app::command_list_window::CommandListState;
+use crate::app::bookmark_editor::{BookmarkEditorState, FIELD_NAMES};
+use crate::app::command_list_window::{display_string, CommandListState};
+use crate::app::stage_inspector::StageInspectorState;
+use crate::app::variable_form::VariableFormState;
 use crate::app::app::{App, WindowState};
+use crate::command_evaluation::RunSummary;
+use crate::lineeditor::EditorState;
+use crate::util::{base64_encode, VecStringExt};
+use unicode_width::UnicodeWidthStr;
 
 use ansi_parser::AnsiParser;
 use crossterm::{
@@ -9,6 +16,7 @@ use crossterm::{
 use std::{
     borrow::Cow,
     io::{self, Write},
+    ops::Range,
 };
 use tui::layout::{Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
@@ -36,7 +44,7 @@ lazy_static! {
     static ref PLAINTEXT_SYNTAX: &'static SyntaxReference = SYNTAX_SET.find_syntax_plain_text();
 }
 
-pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, mut app: &mut App) -> Result<(), failure::Error> {
+pub fn draw_app<B: Backend + Write>(terminal: &mut Terminal<B>, mut app: &mut App) -> Result<(), failure::Error> {
     if let Some((stdin_content, mut should_jump_to_other_cmd)) = app.should_jump_to_other_cmd.take() {
         execute!(io::stdout(), LeaveAlternateScreen)?;
         let mut child = should_jump_to_other_cmd.env("MAN_POSIXLY_CORRECT", "1").spawn()?;
@@ -52,6 +60,12 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, mut app: &mut App) -> Re
         terminal.resize(terminal.size()?)?; // this will redraw the whole screen
     }
 
+    if let Some(text) = app.pending_clipboard.take() {
+        // OSC 52: ask the terminal to put the text into the system clipboard
+        write!(terminal.backend_mut(), "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))?;
+        Write::flush(terminal.backend_mut())?;
+    }
+
     let mut input_field_rect = tui::layout::Rect::new(0, 0, 0, 0);
     terminal.draw(|mut f| {
         let root_rect = f.size();
@@ -73,7 +87,12 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, mut app: &mut App) -> Re
                     .direction(Direction::Vertical)
                     .constraints(
                         [
-                            Length(2 + app.input_state.content_lines().len() as u16),
+                            Length(2 + displayed_command_lines(app).len() as u16),
+                            Length(if app.sample_input_open {
+                                2 + app.sample_input_state.content_lines().len().min(MAX_SAMPLE_INPUT_HEIGHT) as u16
+                            } else {
+                                0
+                            }),
                             Length(if let Some(state) = &app.autocomplete_state {
                                 (state.options.len().min(5) + 2) as u16
                             } else {
@@ -112,20 +131,38 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, mut app: &mut App) -> Re
                     )
                     .highlight_style(Style::default().fg(Color::Black).bg(Color::White))
                     .block(make_default_block("Suggestions", false));
-                    f.render_stateful_widget(list_widget, exec_chunks[1], &mut list_state);
+                    f.render_stateful_widget(list_widget, exec_chunks[2], &mut list_state);
+                }
+                if app.sample_input_open {
+                    draw_sample_input(f, exec_chunks[1], app);
                 }
                 draw_outputs(
                     &mut f,
-                    exec_chunks[2],
+                    exec_chunks[3],
                     app.input_state.content_str() == app.last_executed_cmd,
                     app.is_processing_state,
+                    app.last_run.as_ref(),
                     &app.command_output,
                     &app.command_error,
                 );
 
-                let cursor_x = input_field_rect.x + 1 + app.input_state.displayed_cursor_column() as u16;
-                let cursor_y = input_field_rect.y + 1 + app.input_state.cursor_line as u16;
-                f.set_cursor(cursor_x, cursor_y);
+                if app.sample_input_focused {
+                    let state = &app.sample_input_state;
+                    let cursor_x = exec_chunks[1].x + 1 + state.displayed_cursor_column() as u16;
+                    let cursor_y = exec_chunks[1].y + 1 + (state.cursor_line - sample_input_scroll_offset(state)) as u16;
+                    f.set_cursor(cursor_x, cursor_y);
+                } else if let Some(search) = &app.history_search {
+                    // while searching, the cursor is placed at the match
+                    let lines = displayed_command_lines(app);
+                    let match_start = search.match_range(app.history.entries()).map_or(0, |range| range.start);
+                    let (line, col) = lines.position_of_joined_offset(match_start);
+                    let cursor_x = input_field_rect.x + 1 + UnicodeWidthStr::width(&lines[line][..col]) as u16;
+                    f.set_cursor(cursor_x, input_field_rect.y + 1 + line as u16);
+                } else {
+                    let cursor_x = input_field_rect.x + 1 + app.input_state.displayed_cursor_column() as u16;
+                    let cursor_y = input_field_rect.y + 1 + app.input_state.cursor_line as u16;
+                    f.set_cursor(cursor_x, cursor_y);
+                }
             }
             WindowState::TextView(title, text) => {
                 f.render_widget(
@@ -137,10 +174,13 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, mut app: &mut App) -> Re
                 let always_show_preview = app.config.cmdlist_always_show_preview;
                 draw_command_list(&mut f, root_rect, always_show_preview, listview_state, "Bookmarks");
             }
+            WindowState::BookmarkEditor(editor_state) => draw_bookmark_editor(f, root_rect, editor_state),
+            WindowState::VariableForm(form_state) => draw_variable_form(f, root_rect, form_state),
             WindowState::HistoryList(listview_state) => {
                 let always_show_preview = app.config.cmdlist_always_show_preview;
                 draw_command_list(&mut f, root_rect, always_show_preview, listview_state, "History");
             }
+            WindowState::StageInspector(inspector_state) => draw_stage_inspector(f, root_rect, inspector_state),
         }
 
         f.render_widget(
@@ -153,25 +193,64 @@ pub fn draw_app<B: Backend>(terminal: &mut Terminal<B>, mut app: &mut App) -> Re
 }
 
 fn draw_command_list<B: Backend>(f: &mut Frame<B>, rect: Rect, always_show_preview: bool, state: &CommandListState, title: &str) {
-    let show_preview = always_show_preview || state.selected_entry().map(|e| e.lines().len() > 1) == Some(true);
+    let show_preview = always_show_preview
+        || state
+            .selected_entry()
+            .map(|e| e.lines().len() > 1 || !e.metadata.is_empty() || !e.bookmark.description.is_empty())
+            == Some(true);
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([Percentage(if show_preview { 60 } else { 100 }), Percentage(100)].as_ref())
         .split(rect);
 
-    let items = state
-        .list
-        .iter()
-        .map(|entry| entry.as_string().replace("\n", " ↵ "))
-        .map(|entry| ListItem::new(Span::raw(entry)))
-        .collect::<Vec<_>>();
+    let match_style = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+    let group_style = Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD);
+    // when there are shared bookmark collections, entries are grouped under the name of their collection.
+    // Filtered entries are sorted by how well they match instead, so they aren't grouped.
+    let is_filtered = state.filter.as_deref().is_some_and(|query| !query.is_empty());
+    let show_groups = !is_filtered && state.list.iter().any(|entry| entry.bookmark.collection.is_some());
+
+    let mut items = Vec::new();
+    let mut selected_item = None;
+    let mut current_group = None;
+    for (idx, entry, matched_positions) in state.visible_entries() {
+        let group = entry.bookmark.collection.as_deref().unwrap_or("Personal");
+        if show_groups && current_group != Some(group) {
+            items.push(ListItem::new(Span::styled(format!("── {} ──", group), group_style)));
+            current_group = Some(group);
+        }
+        if state.selected_idx == Some(idx) {
+            selected_item = Some(items.len());
+        }
+
+        // split the entry into runs of matched and unmatched characters
+        let spans = display_string(entry)
+            .chars()
+            .enumerate()
+            .group_by(|(idx, _)| matched_positions.contains(idx))
+            .into_iter()
+            .map(|(is_match, chars)| {
+                let text = chars.map(|(_, c)| c).collect::<String>();
+                if is_match {
+                    Span::styled(text, match_style)
+                } else {
+                    Span::raw(text)
+                }
+            })
+            .collect_vec();
+        items.push(ListItem::new(Spans::from(spans)));
+    }
 
     let mut list_state = ListState::default();
-    list_state.select(state.selected_idx);
+    list_state.select(selected_item);
 
+    let title = match &state.filter {
+        Some(query) => format!("{} [/{}]", title, query),
+        None => format!("{} [/ to filter]", title),
+    };
     let list_widget = List::new(items)
-        .block(make_default_block(title, true))
+        .block(make_default_block(&title, true))
         .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
         .highlight_symbol(">>");
 
@@ -179,14 +258,129 @@ fn draw_command_list<B: Backend>(f: &mut Frame<B>, rect: Rect, always_show_previ
 
     if show_preview {
         if let Some(selected_content) = state.selected_entry() {
+            let preview_title = match &selected_content.last_run {
+                Some(summary) => format!("Preview [{}]", summary),
+                None => "Preview".to_string(),
+            };
+            let mut lines = Vec::new();
+            if !selected_content.bookmark.description.is_empty() {
+                let description_style = Style::default().add_modifier(Modifier::ITALIC);
+                lines.push(Spans::from(Span::styled(
+                    selected_content.bookmark.description.as_str(),
+                    description_style,
+                )));
+            }
+            if !selected_content.metadata.is_empty() {
+                let metadata_style = Style::default().fg(Color::DarkGray);
+                lines.push(Spans::from(Span::styled(selected_content.metadata.to_string(), metadata_style)));
+            }
+            lines.extend(selected_content.lines().iter().map(|line| Spans::from(line.as_str())));
             f.render_widget(
-                Paragraph::new(selected_content.as_string().as_str()).block(make_default_block("Preview", false)),
+                Paragraph::new(Text::from(lines)).block(make_default_block(&preview_title, false)),
                 chunks[1],
             );
         }
     }
 }
 
+fn draw_bookmark_editor<B: Backend>(f: &mut Frame<B>, rect: Rect, state: &BookmarkEditorState) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Length(3), Length(3), Length(3), Percentage(100)].as_ref())
+        .split(rect);
+
+    for (idx, (field, name)) in state.fields.iter().zip(FIELD_NAMES.iter()).enumerate() {
+        let is_focused = idx == state.focused_field;
+        let title = if name == &"Tags" { "Tags (separated by commas)" } else { name };
+        let content = field.content_str();
+        f.render_widget(
+            Paragraph::new(content.as_str()).block(make_default_block(title, is_focused)),
+            chunks[idx],
+        );
+        if is_focused {
+            f.set_cursor(chunks[idx].x + 1 + field.displayed_cursor_column() as u16, chunks[idx].y + 1);
+        }
+    }
+
+    let command = state.list_state().selected_entry().map(|entry| entry.as_string()).unwrap_or_default();
+    f.render_widget(
+        Paragraph::new(command.as_str()).block(make_default_block(
+            "Command [Enter: save, Esc: cancel, Tab: next field]",
+            false,
+        )),
+        chunks[3],
+    );
+}
+
+fn draw_variable_form<B: Backend>(f: &mut Frame<B>, rect: Rect, state: &VariableFormState) {
+    let constraints = state
+        .fields
+        .iter()
+        .map(|_| Length(3))
+        .chain(std::iter::once(Percentage(100)))
+        .collect_vec();
+    let chunks = Layout::default().direction(Direction::Vertical).constraints(constraints).split(rect);
+
+    for (idx, (field, variable)) in state.fields.iter().zip(&state.variables).enumerate() {
+        let is_focused = idx == state.focused_field;
+        let content = field.content_str();
+        f.render_widget(
+            Paragraph::new(content.as_str()).block(make_default_block(&variable.name, is_focused)),
+            chunks[idx],
+        );
+        if is_focused {
+            f.set_cursor(chunks[idx].x + 1 + field.displayed_cursor_column() as u16, chunks[idx].y + 1);
+        }
+    }
+
+    let resolved = state.resolved_lines().join("\n");
+    f.render_widget(
+        Paragraph::new(resolved.as_str()).block(make_default_block(
+            "Command [Enter: load, Esc: cancel, Tab: next variable]",
+            false,
+        )),
+        chunks[state.fields.len()],
+    );
+}
+
+/// maximum amount of pipeline stages that are shown next to each other in the stage inspector
+const MAX_VISIBLE_STAGES: usize = 3;
+
+fn draw_stage_inspector<B: Backend>(f: &mut Frame<B>, rect: Rect, state: &StageInspectorState) {
+    if state.stages.is_empty() {
+        f.render_widget(
+            Paragraph::new("There is no command to inspect").block(make_default_block("Stages", true)),
+            rect,
+        );
+        return;
+    }
+
+    let visible_stages = state.stages.iter().enumerate().skip(state.scroll_offset).take(MAX_VISIBLE_STAGES);
+    let column_count = (state.stages.len() - state.scroll_offset).min(MAX_VISIBLE_STAGES);
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Ratio(1, column_count as u32); column_count])
+        .split(rect);
+
+    for ((idx, stage), column) in visible_stages.zip(columns) {
+        let (title, text) = match &stage.output {
+            None => (
+                format!("{}: {} (running)", idx + 1, stage.stage_command),
+                Text::from(Span::styled("running...", Style::default().fg(Color::Yellow))),
+            ),
+            Some(Ok(lines)) => (
+                format!("{}: {} ({} lines)", idx + 1, stage.stage_command, lines.len()),
+                Text::from(lines.iter().take(column.height as usize).map(|line| Spans::from(line.as_str())).collect_vec()),
+            ),
+            Some(Err(err)) => (
+                format!("{}: {} (failed)", idx + 1, stage.stage_command),
+                Text::from(Span::styled(err.as_str(), Style::default().fg(Color::Red))),
+            ),
+        };
+        f.render_widget(Paragraph::new(text).block(make_default_block(&title, false)), column);
+    }
+}
+
 fn draw_input_field<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
     // TODO this is hideously inefficient
     //      also make themes configurable?
@@ -194,10 +388,10 @@ fn draw_input_field<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
 
     let mut highlighter = HighlightLines::new(*SH_SYNTAX, &THEME);
 
+    let command_lines = displayed_command_lines(app);
+
     // cut off lines at the input field width, adding ...
-    let lines: Vec<String> = app
-        .input_state
-        .content_lines()
+    let lines: Vec<String> = command_lines
         .iter()
         .map(|line| truncate_with_ellipsis(line.clone(), rect.width as usize))
         .collect_vec();
@@ -212,32 +406,136 @@ fn draw_input_field<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &mut App) {
                     .map(|(style, part)| Span::styled(*part, highlight_style_to_tui_style(&style)))
                     .collect_vec()
             })
-            .map(Spans::from)
             .collect_vec()
     } else {
-        lines.iter().map(Span::raw).map(Spans::from).collect_vec()
+        lines.iter().map(|line| vec![Span::raw(line.as_str())]).collect_vec()
+    };
+
+    let selection = match &app.history_search {
+        Some(search) => search.match_range(app.history.entries()),
+        None => app
+            .vi_state
+            .as_ref()
+            .and_then(|vi_state| vi_state.visual_selection(&app.input_state))
+            .or_else(|| app.input_state.selection()),
+    };
+    let styled_lines = match selection {
+        Some(selection) => {
+            let mut line_start = 0;
+            styled_lines
+                .into_iter()
+                .zip(&command_lines)
+                .map(|(spans, line)| {
+                    let spans = highlight_selection(spans, line_start, &selection);
+                    line_start += line.len() + 1;
+                    spans
+                })
+                .map(Spans::from)
+                .collect_vec()
+        }
+        None => styled_lines.into_iter().map(Spans::from).collect_vec(),
     };
 
     let is_bookmarked = app.bookmarks.entries().contains(&app.input_state.content_to_commandentry());
 
+    let io_error = app
+        .history
+        .io_error
+        .as_ref()
+        .or(app.bookmarks.io_error.as_ref())
+        .or(app.collection_error.as_ref());
+
     let input_block_title = format!(
-        "Command{}{}{}{}",
+        "{}{}{}{}{}{}{}{}",
+        match &app.history_search {
+            Some(search) if search.match_idx.is_none() && !search.query.is_empty() => {
+                format!("(failing reverse-i-search)`{}`", search.query)
+            }
+            Some(search) => format!("(reverse-i-search)`{}`", search.query),
+            None => "Command".to_string(),
+        },
+        app.vi_state.as_ref().map(|vi_state| format!(" [{}]", vi_state.mode)).unwrap_or_default(),
         if is_bookmarked { " [Bookmarked]" } else { "" },
         if app.autoeval_mode { " [Autoeval]" } else { "" },
         if app.cached_command_part.is_some() { " [Caching]" } else { "" },
+        if app.piped_input_reader.as_ref().is_some_and(|reader| reader.truncated) {
+            " [Input truncated]"
+        } else {
+            ""
+        },
         if app.autoeval_mode && app.paranoid_history_mode {
             " [Paranoid]"
         } else {
             ""
-        }
+        },
+        io_error.map(|err| format!(" [Error: {}]", err)).unwrap_or_default()
+    );
+
+    f.render_widget(
+        Paragraph::new(Text::from(styled_lines)).block(make_default_block(&input_block_title, !app.sample_input_focused)),
+        rect,
     );
+}
+
+/// the lines shown in the command field: the matched entry while searching the history, or the command being edited
+fn displayed_command_lines(app: &App) -> Vec<String> {
+    match &app.history_search {
+        Some(search) => search
+            .matched_entry(app.history.entries())
+            .map(|entry| entry.lines().clone())
+            .unwrap_or_else(|| vec![String::new()]),
+        None => app.input_state.content_lines().clone(),
+    }
+}
 
+/// maximum amount of lines of the sample input that are shown at once
+const MAX_SAMPLE_INPUT_HEIGHT: usize = 8;
+
+fn draw_sample_input<B: Backend>(f: &mut Frame<B>, rect: Rect, app: &App) {
+    let state = &app.sample_input_state;
+    let lines = state
+        .content_lines()
+        .iter()
+        .skip(sample_input_scroll_offset(state))
+        .take(MAX_SAMPLE_INPUT_HEIGHT)
+        .map(|line| Spans::from(truncate_with_ellipsis(line.clone(), rect.width as usize)))
+        .collect_vec();
+    let title = format!("Sample input [{} lines piped into the command]", state.content_lines().len());
     f.render_widget(
-        Paragraph::new(Text::from(styled_lines)).block(make_default_block(&input_block_title, true)),
+        Paragraph::new(Text::from(lines)).block(make_default_block(&title, app.sample_input_focused)),
         rect,
     );
 }
 
+/// the first line of the sample input that is visible, so the cursor is always shown
+fn sample_input_scroll_offset(state: &EditorState) -> usize {
+    (state.cursor_line + 1).saturating_sub(MAX_SAMPLE_INPUT_HEIGHT)
+}
+
+/// reverse the colors of the parts of a line within the selected range.
+/// The selection is given as a byte range within all lines joined with `\n`, line_start is the offset of this line.
+fn highlight_selection<'a>(spans: Vec<Span<'a>>, line_start: usize, selection: &Range<usize>) -> Vec<Span<'a>> {
+    let mut span_start = line_start;
+    let mut highlighted = Vec::new();
+    for span in spans {
+        let content = span.content.as_ref();
+        let span_end = span_start + content.len();
+        let selected_start = selection.start.clamp(span_start, span_end) - span_start;
+        let selected_end = selection.end.clamp(span_start, span_end) - span_start;
+        if selected_start == selected_end || !content.is_char_boundary(selected_start) || !content.is_char_boundary(selected_end) {
+            span_start = span_end;
+            highlighted.push(span);
+            continue;
+        }
+        let selected_style = span.style.add_modifier(Modifier::REVERSED);
+        highlighted.push(Span::styled(content[..selected_start].to_string(), span.style));
+        highlighted.push(Span::styled(content[selected_start..selected_end].to_string(), selected_style));
+        highlighted.push(Span::styled(content[selected_end..].to_string(), span.style));
+        span_start = span_end;
+    }
+    highlighted
+}
+
 fn truncate_with_ellipsis(mut line: String, length: usize) -> String {
     if line.len() > length - 5 {
         line.truncate(length - 5);
@@ -295,6 +593,7 @@ fn draw_outputs<B: Backend>(
     rect: Rect,
     changed: bool,
     processing_state: Option<u8>,
+    last_run: Option<&RunSummary>,
     stdout: &str,
     stderr: &str,
 ) {
@@ -320,9 +619,10 @@ fn draw_outputs<B: Backend>(
         .split(rect);
 
     let stdout_title = format!(
-        "Output{}{}",
+        "Output{}{}{}",
         if changed { "" } else { " [+]" },
-        display_processing_state(processing_state)
+        display_processing_state(processing_state),
+        last_run.map(|summary| format!(" [{}]", summary)).unwrap_or_default()
     );
 
     // TODO only render the amount of lines that is actually visible, or make it scrollable
@@ -70,6 +70,10 @@ impl<T: AsRef<str>> StringExt for T {
 
 pub trait VecStringExt {
     fn split_strings_at_offset(&self, line_offset: usize, col_offset: usize) -> (Vec<String>, Vec<String>);
+    /// the byte offset of the given position in the lines joined with `\n`
+    fn joined_offset_of(&self, line: usize, col: usize) -> usize;
+    /// the line and column of a byte offset in the lines joined with `\n`
+    fn position_of_joined_offset(&self, offset: usize) -> (usize, usize);
 }
 
 impl VecStringExt for Vec<String> {
@@ -99,6 +103,21 @@ impl VecStringExt for Vec<String> {
 
         (left_side, right_side)
     }
+
+    fn joined_offset_of(&self, line: usize, col: usize) -> usize {
+        self.iter().take(line).map(|line| line.len() + 1).sum::<usize>() + col
+    }
+
+    fn position_of_joined_offset(&self, offset: usize) -> (usize, usize) {
+        let mut remaining = offset;
+        for (line_nr, line) in self.iter().enumerate() {
+            if remaining <= line.len() || line_nr == self.len() - 1 {
+                return (line_nr, remaining.min(line.len()));
+            }
+            remaining -= line.len() + 1;
+        }
+        (0, 0)
+    }
 }
 
 #[cfg(test)]
@@ -120,6 +139,16 @@ mod vecstringext_test {
         }
     }
 
+    #[test]
+    fn test_joined_offset_of() {
+        let base_lines: Vec<String> = vec!["abcd".into(), "".into(), "äb".into()];
+        assert_eq!(base_lines.joined_offset_of(0, 0), 0);
+        assert_eq!(base_lines.joined_offset_of(0, 3), 3);
+        assert_eq!(base_lines.joined_offset_of(1, 0), 5);
+        assert_eq!(base_lines.joined_offset_of(2, 2), 8);
+        assert_eq!(&base_lines.join("\n")[8..], "b");
+    }
+
     #[test]
     fn test_at_bounds() {
         {
@@ -136,3 +165,34 @@ mod vecstringext_test {
         }
     }
 }
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// encode bytes as standard, padded base64
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (idx, &byte)| group | (byte as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod base64_test {
+    use super::*;
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode("sed 's/ä//'".as_bytes()), "c2VkICdzL8OkLy8n");
+    }
+}
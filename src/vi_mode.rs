@@ -0,0 +1,918 @@
+use crate::keybindings::Keybindings;
+use crate::lineeditor::{EditorEvent, EditorState};
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+/// counts are capped at this, so a huge count can't make a command run (nearly) forever
+const MAX_COUNT: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViMode {
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl Display for ViMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ViMode::Normal => write!(f, "NORMAL"),
+            ViMode::Insert => write!(f, "INSERT"),
+            ViMode::Visual => write!(f, "VISUAL"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FindForward(char),
+    FindBackward(char),
+    TillForward(char),
+    TillBackward(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Move(Motion),
+    Operate(Operator, Option<Motion>),
+    /// a single-key command like `x`, `p` or `i`
+    Simple(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct NormalCommand {
+    count: Option<usize>,
+    action: Action,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Parsed {
+    Incomplete,
+    Invalid,
+    Command(NormalCommand),
+}
+
+/// the characters between `from` and `to` of a motion, which operators act on
+struct MotionTarget {
+    to: usize,
+    /// whether the character the motion ends on is included
+    inclusive: bool,
+    /// whether the motion covers whole lines
+    linewise: bool,
+}
+
+/// the count and keys of a change, so it can be repeated
+type RecordedChange = (Option<usize>, Vec<(KeyCode, KeyModifiers)>);
+
+/// State of the vi-style modal editing, applied on top of an `EditorState`
+#[derive(Debug, Clone)]
+pub struct ViState {
+    pub mode: ViMode,
+    /// keys of the normal mode command that is currently being typed, like `d2`
+    pending: Vec<char>,
+    /// where visual mode was started, as a char index
+    visual_anchor: usize,
+    /// the text that was last deleted or yanked, and whether it consists of whole lines
+    register: (String, bool),
+    /// the last change, repeated by `.`
+    last_change: Option<RecordedChange>,
+    /// a change that is still going on in insert mode
+    recording: Option<RecordedChange>,
+    is_replaying: bool,
+}
+
+impl ViState {
+    pub fn new() -> ViState {
+        ViState {
+            mode: ViMode::Insert,
+            pending: Vec::new(),
+            visual_anchor: 0,
+            register: (String::new(), false),
+            last_change: None,
+            recording: None,
+            is_replaying: false,
+        }
+    }
+
+    /// the selected range of visual mode, as byte offsets within the editors content joined with `\n`
+    pub fn visual_selection(&self, editor: &EditorState) -> Option<Range<usize>> {
+        if self.mode != ViMode::Visual {
+            return None;
+        }
+        let text = editor.content_lines().join("\n");
+        let chars = text.chars().collect::<Vec<_>>();
+        let (start, end) = self.visual_range(&chars, char_idx(&text, editor.cursor_offset()));
+        Some(byte_offset(&text, start)..byte_offset(&text, end))
+    }
+
+    pub fn handle_key(&mut self, editor: &mut EditorState, bindings: &Keybindings, code: KeyCode, modifiers: KeyModifiers) {
+        match self.mode {
+            ViMode::Insert => self.handle_insert_key(editor, bindings, code, modifiers),
+            ViMode::Normal | ViMode::Visual => self.handle_normal_key(editor, bindings, code, modifiers),
+        }
+    }
+
+    fn handle_insert_key(&mut self, editor: &mut EditorState, bindings: &Keybindings, code: KeyCode, modifiers: KeyModifiers) {
+        if let Some((_, keys)) = self.recording.as_mut() {
+            keys.push((code, modifiers));
+        }
+        if code == KeyCode::Esc {
+            self.mode = ViMode::Normal;
+            if let Some(change) = self.recording.take() {
+                if !self.is_replaying {
+                    self.last_change = Some(change);
+                }
+            }
+            editor.apply_event(EditorEvent::GoLeft);
+            clamp_cursor(editor);
+        } else if let Some(event) = bindings.editor_event(code, modifiers) {
+            editor.apply_event(event);
+        }
+    }
+
+    fn handle_normal_key(&mut self, editor: &mut EditorState, bindings: &Keybindings, code: KeyCode, modifiers: KeyModifiers) {
+        let key = match code {
+            KeyCode::Char('r') if modifiers.contains(KeyModifiers::CONTROL) => {
+                self.pending.clear();
+                editor.apply_event(EditorEvent::Redo);
+                clamp_cursor(editor);
+                return;
+            }
+            KeyCode::Esc => {
+                self.pending.clear();
+                self.mode = ViMode::Normal;
+                return;
+            }
+            KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => c,
+            KeyCode::Left | KeyCode::Backspace => 'h',
+            KeyCode::Right => 'l',
+            KeyCode::Up => 'k',
+            KeyCode::Down => 'j',
+            KeyCode::Home => '0',
+            KeyCode::End => '$',
+            _ => return,
+        };
+        self.pending.push(key);
+
+        let parsed = if self.mode == ViMode::Visual {
+            parse_visual_command(&self.pending)
+        } else {
+            parse_normal_command(&self.pending)
+        };
+        let command = match parsed {
+            Parsed::Incomplete => return,
+            Parsed::Invalid => {
+                self.pending.clear();
+                return;
+            }
+            Parsed::Command(command) => command,
+        };
+        let keys = std::mem::take(&mut self.pending);
+
+        if self.mode == ViMode::Visual {
+            self.execute_visual_command(editor, command);
+        } else {
+            if command.action == Action::Simple('.') {
+                self.repeat_last_change(editor, bindings, command.count);
+                return;
+            }
+            self.execute_normal_command(editor, command);
+            if is_change(command.action) && !self.is_replaying {
+                // strip the counts, their product is remembered separately so `.` can replace it
+                let keys = keys_without_counts(&keys)
+                    .into_iter()
+                    .map(|c| (KeyCode::Char(c), KeyModifiers::NONE))
+                    .collect();
+                if self.mode == ViMode::Insert {
+                    self.recording = Some((command.count, keys));
+                } else {
+                    self.last_change = Some((command.count, keys));
+                }
+            }
+        }
+        if self.mode != ViMode::Insert {
+            clamp_cursor(editor);
+        }
+    }
+
+    fn repeat_last_change(&mut self, editor: &mut EditorState, bindings: &Keybindings, count: Option<usize>) {
+        let (original_count, keys) = match self.last_change.clone() {
+            Some(change) => change,
+            None => return,
+        };
+        let count_keys = count.or(original_count).map(|count| count.to_string()).unwrap_or_default();
+        self.is_replaying = true;
+        for c in count_keys.chars() {
+            self.handle_key(editor, bindings, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        for (code, modifiers) in keys {
+            self.handle_key(editor, bindings, code, modifiers);
+        }
+        if self.mode == ViMode::Insert {
+            self.handle_key(editor, bindings, KeyCode::Esc, KeyModifiers::NONE);
+        }
+        self.is_replaying = false;
+    }
+
+    fn execute_normal_command(&mut self, editor: &mut EditorState, command: NormalCommand) {
+        let text = editor.content_lines().join("\n");
+        let chars = text.chars().collect::<Vec<_>>();
+        let cursor = char_idx(&text, editor.cursor_offset());
+        let count = command.count.unwrap_or(1);
+
+        match command.action {
+            Action::Move(motion) => {
+                if let Some(target) = apply_motion(&chars, cursor, motion, count) {
+                    editor.set_cursor_offset(byte_offset(&text, target.to));
+                }
+            }
+            Action::Operate(operator, motion) => {
+                let motion = match (operator, motion) {
+                    // `cw` behaves like `ce` when on a word, like in vim
+                    (Operator::Change, Some(Motion::WordForward)) if !is_blank(chars.get(cursor)) => Some(Motion::WordEnd),
+                    _ => motion,
+                };
+                let range = match motion {
+                    Some(motion) => match apply_motion(&chars, cursor, motion, count) {
+                        Some(target) => operator_range(&chars, cursor, &target, motion),
+                        None => return,
+                    },
+                    // doubled operators like `dd` act on `count` lines
+                    None => {
+                        let last_line_start = (1..count.min(chars.len() + 1)).fold(line_start(&chars, cursor), |start, _| {
+                            (line_end(&chars, start) + 1).min(chars.len())
+                        });
+                        linewise_range(&chars, cursor, last_line_start)
+                    }
+                };
+                let linewise = motion.map(|motion| motion == Motion::Up || motion == Motion::Down).unwrap_or(true);
+                self.operate(editor, &text, &chars, operator, range, linewise);
+            }
+            Action::Simple(c) => self.execute_simple_command(editor, &text, &chars, cursor, c, count),
+        }
+    }
+
+    fn execute_simple_command(
+        &mut self,
+        editor: &mut EditorState,
+        text: &str,
+        chars: &[char],
+        cursor: usize,
+        c: char,
+        count: usize,
+    ) {
+        let normal_command = |action| NormalCommand {
+            count: Some(count),
+            action,
+        };
+        match c {
+            'i' => self.mode = ViMode::Insert,
+            'a' => {
+                editor.set_cursor_offset(byte_offset(text, (cursor + 1).min(line_end(chars, cursor))));
+                self.mode = ViMode::Insert;
+            }
+            'I' => {
+                editor.set_cursor_offset(byte_offset(text, first_non_blank(chars, cursor)));
+                self.mode = ViMode::Insert;
+            }
+            'A' => {
+                editor.set_cursor_offset(byte_offset(text, line_end(chars, cursor)));
+                self.mode = ViMode::Insert;
+            }
+            'o' => {
+                let end = byte_offset(text, line_end(chars, cursor));
+                editor.replace_range(end..end, "\n");
+                editor.group_following_inserts();
+                self.mode = ViMode::Insert;
+            }
+            'O' => {
+                let start = byte_offset(text, line_start(chars, cursor));
+                editor.replace_range(start..start, "\n");
+                editor.set_cursor_offset(start);
+                editor.group_following_inserts();
+                self.mode = ViMode::Insert;
+            }
+            'x' => self.execute_normal_command(editor, normal_command(Action::Operate(Operator::Delete, Some(Motion::Right)))),
+            'X' => self.execute_normal_command(editor, normal_command(Action::Operate(Operator::Delete, Some(Motion::Left)))),
+            's' => self.execute_normal_command(editor, normal_command(Action::Operate(Operator::Change, Some(Motion::Right)))),
+            'D' => self.execute_normal_command(editor, normal_command(Action::Operate(Operator::Delete, Some(Motion::LineEnd)))),
+            'C' => self.execute_normal_command(editor, normal_command(Action::Operate(Operator::Change, Some(Motion::LineEnd)))),
+            'p' | 'P' => {
+                let (register, linewise) = self.register.clone();
+                if register.is_empty() {
+                    return;
+                }
+                let pasted = register.repeat(count);
+                if linewise {
+                    // whole lines are pasted below or above the current line
+                    if c == 'p' {
+                        let end = byte_offset(text, line_end(chars, cursor));
+                        editor.replace_range(end..end, &format!("\n{}", pasted.trim_end_matches('\n')));
+                        editor.set_cursor_offset(end + 1);
+                    } else {
+                        let start = byte_offset(text, line_start(chars, cursor));
+                        editor.replace_range(start..start, &pasted);
+                        editor.set_cursor_offset(start);
+                    }
+                } else {
+                    let position = if c == 'p' {
+                        (cursor + 1).min(line_end(chars, cursor))
+                    } else {
+                        cursor
+                    };
+                    let position = byte_offset(text, position);
+                    editor.replace_range(position..position, &pasted);
+                    // the cursor ends up on the last pasted character
+                    editor.set_cursor_offset(position + pasted.len() - pasted.chars().last().map(char::len_utf8).unwrap_or(0));
+                }
+            }
+            'u' => (0..count).for_each(|_| editor.apply_event(EditorEvent::Undo)),
+            'v' => {
+                self.mode = ViMode::Visual;
+                self.visual_anchor = cursor;
+            }
+            _ => {}
+        }
+    }
+
+    fn execute_visual_command(&mut self, editor: &mut EditorState, command: NormalCommand) {
+        let text = editor.content_lines().join("\n");
+        let chars = text.chars().collect::<Vec<_>>();
+        let cursor = char_idx(&text, editor.cursor_offset());
+        match command.action {
+            Action::Move(motion) => {
+                if let Some(target) = apply_motion(&chars, cursor, motion, command.count.unwrap_or(1)) {
+                    editor.set_cursor_offset(byte_offset(&text, target.to));
+                }
+            }
+            Action::Operate(operator, _) => {
+                self.mode = ViMode::Normal;
+                let range = self.visual_range(&chars, cursor);
+                self.operate(editor, &text, &chars, operator, range, false);
+            }
+            Action::Simple(_) => self.mode = ViMode::Normal,
+        }
+    }
+
+    /// the selected characters, from the anchor to the cursor, inclusive
+    fn visual_range(&self, chars: &[char], cursor: usize) -> (usize, usize) {
+        let start = self.visual_anchor.min(cursor);
+        let end = (self.visual_anchor.max(cursor) + 1).min(chars.len());
+        (start, end)
+    }
+
+    /// apply an operator to the given range of chars
+    fn operate(
+        &mut self,
+        editor: &mut EditorState,
+        text: &str,
+        chars: &[char],
+        operator: Operator,
+        (start, end): (usize, usize),
+        linewise: bool,
+    ) {
+        if start == end && !linewise {
+            return;
+        }
+        let byte_range = byte_offset(text, start)..byte_offset(text, end);
+        let mut yanked = text[byte_range.clone()].to_string();
+        if linewise && !yanked.ends_with('\n') {
+            yanked.push('\n');
+        }
+        let yanked = yanked.trim_start_matches(|c| c == '\n' && linewise).to_string();
+        self.register = (yanked, linewise);
+
+        match operator {
+            Operator::Yank => editor.set_cursor_offset(byte_range.start),
+            Operator::Delete => {
+                editor.replace_range(byte_range, "");
+                if linewise {
+                    let text = editor.content_lines().join("\n");
+                    let chars = text.chars().collect::<Vec<_>>();
+                    let cursor = char_idx(&text, editor.cursor_offset());
+                    editor.set_cursor_offset(byte_offset(&text, first_non_blank(&chars, cursor)));
+                }
+            }
+            Operator::Change => {
+                // changing lines keeps an empty line to type into
+                let byte_range = if linewise {
+                    // the range of the last line starts with the newline before it
+                    let first_line = if end == chars.len() && chars.get(start) == Some(&'\n') {
+                        start + 1
+                    } else {
+                        start
+                    };
+                    byte_offset(text, first_line)..byte_offset(text, line_end(chars, first_line.max(end.saturating_sub(1))))
+                } else {
+                    byte_range
+                };
+                if !byte_range.is_empty() {
+                    editor.replace_range(byte_range, "");
+                    editor.group_following_inserts();
+                }
+                self.mode = ViMode::Insert;
+            }
+        }
+    }
+}
+
+fn is_change(action: Action) -> bool {
+    match action {
+        Action::Move(_) => false,
+        Action::Operate(operator, _) => operator != Operator::Yank,
+        Action::Simple(c) => "iaIAoOxXsDCpP".contains(c),
+    }
+}
+
+/// parse an optional count, returning it and the remaining keys
+fn parse_count(keys: &[char]) -> (Option<usize>, &[char]) {
+    let digits = keys
+        .iter()
+        .enumerate()
+        .take_while(|(idx, c)| c.is_ascii_digit() && !(*idx == 0 && **c == '0'))
+        .count();
+    let count = keys[..digits].iter().fold(None, |count: Option<usize>, c| {
+        let digit = c.to_digit(10).unwrap_or(0) as usize;
+        Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit))
+    });
+    (count.map(|count| count.min(MAX_COUNT)), &keys[digits..])
+}
+
+/// the keys of a complete command without the count in front of it and in front of its motion
+fn keys_without_counts(keys: &[char]) -> Vec<char> {
+    let (_, rest) = parse_count(keys);
+    match rest.split_first() {
+        Some((operator, motion_keys)) if "dcy".contains(*operator) => {
+            std::iter::once(*operator).chain(parse_count(motion_keys).1.iter().copied()).collect()
+        }
+        _ => rest.to_vec(),
+    }
+}
+
+/// parse a motion, returning None if the keys are not a (complete) motion
+fn parse_motion(keys: &[char]) -> Result<Option<Motion>, ()> {
+    let motion = match keys {
+        [] => return Ok(None),
+        ['h'] => Motion::Left,
+        ['l'] | [' '] => Motion::Right,
+        ['k'] => Motion::Up,
+        ['j'] => Motion::Down,
+        ['w'] => Motion::WordForward,
+        ['b'] => Motion::WordBackward,
+        ['e'] => Motion::WordEnd,
+        ['0'] => Motion::LineStart,
+        ['^'] => Motion::FirstNonBlank,
+        ['$'] => Motion::LineEnd,
+        ['f'] | ['F'] | ['t'] | ['T'] => return Ok(None),
+        ['f', c] => Motion::FindForward(*c),
+        ['F', c] => Motion::FindBackward(*c),
+        ['t', c] => Motion::TillForward(*c),
+        ['T', c] => Motion::TillBackward(*c),
+        _ => return Err(()),
+    };
+    Ok(Some(motion))
+}
+
+fn parse_normal_command(keys: &[char]) -> Parsed {
+    let (count, rest) = parse_count(keys);
+    let operator = match rest.first() {
+        None => return Parsed::Incomplete,
+        Some('d') => Some(Operator::Delete),
+        Some('c') => Some(Operator::Change),
+        Some('y') => Some(Operator::Yank),
+        Some(_) => None,
+    };
+    let action = match operator {
+        Some(operator) => {
+            let (motion_count, motion_keys) = parse_count(&rest[1..]);
+            let count = match (count, motion_count) {
+                (None, None) => None,
+                (count, motion_count) => Some(count.unwrap_or(1).saturating_mul(motion_count.unwrap_or(1)).min(MAX_COUNT)),
+            };
+            let action = if motion_keys.first() == rest.first() && motion_keys.len() == 1 {
+                Action::Operate(operator, None)
+            } else {
+                match parse_motion(motion_keys) {
+                    Ok(Some(motion)) => Action::Operate(operator, Some(motion)),
+                    Ok(None) => return Parsed::Incomplete,
+                    Err(()) => return Parsed::Invalid,
+                }
+            };
+            return Parsed::Command(NormalCommand { count, action });
+        }
+        None => match parse_motion(rest) {
+            Ok(Some(motion)) => Action::Move(motion),
+            Ok(None) => return Parsed::Incomplete,
+            Err(()) if rest.len() == 1 && "iaIAoOxXsDCpPuv.".contains(rest[0]) => Action::Simple(rest[0]),
+            Err(()) => return Parsed::Invalid,
+        },
+    };
+    Parsed::Command(NormalCommand { count, action })
+}
+
+/// in visual mode, operators act on the selection right away
+fn parse_visual_command(keys: &[char]) -> Parsed {
+    let (count, rest) = parse_count(keys);
+    let operator = match rest {
+        ['d'] | ['x'] => Operator::Delete,
+        ['c'] | ['s'] => Operator::Change,
+        ['y'] => Operator::Yank,
+        ['v'] => {
+            return Parsed::Command(NormalCommand {
+                count,
+                action: Action::Simple('v'),
+            })
+        }
+        _ => {
+            return match parse_motion(rest) {
+                Ok(Some(motion)) => Parsed::Command(NormalCommand {
+                    count,
+                    action: Action::Move(motion),
+                }),
+                Ok(None) => Parsed::Incomplete,
+                Err(()) => Parsed::Invalid,
+            }
+        }
+    };
+    Parsed::Command(NormalCommand {
+        count,
+        action: Action::Operate(operator, None),
+    })
+}
+
+/// the range an operator acts on, given the motion it was combined with
+fn operator_range(chars: &[char], cursor: usize, target: &MotionTarget, motion: Motion) -> (usize, usize) {
+    if target.linewise {
+        return linewise_range(chars, cursor, target.to);
+    }
+    let (start, end) = if target.to < cursor {
+        (target.to, cursor)
+    } else {
+        (cursor, target.to)
+    };
+    let end = if target.inclusive { (end + 1).min(chars.len()) } else { end };
+    // like in vim, `dw` on the last word of a line doesn't join it with the next one
+    if motion == Motion::WordForward {
+        if let Some(newline) = chars[start..end].iter().position(|&c| c == '\n') {
+            if newline > 0 {
+                return (start, start + newline);
+            }
+        }
+    }
+    (start, end)
+}
+
+/// the range of all lines between the given positions, including one of their surrounding newlines
+fn linewise_range(chars: &[char], a: usize, b: usize) -> (usize, usize) {
+    let start = line_start(chars, a.min(b));
+    let end = line_end(chars, a.max(b));
+    if end < chars.len() {
+        (start, end + 1)
+    } else {
+        (start.saturating_sub(1), end)
+    }
+}
+
+fn apply_motion(chars: &[char], cursor: usize, motion: Motion, count: usize) -> Option<MotionTarget> {
+    // no motion can take more steps than there are characters
+    let count = count.min(chars.len() + 1);
+    let exclusive = |to| MotionTarget {
+        to,
+        inclusive: false,
+        linewise: false,
+    };
+    let inclusive = |to| MotionTarget {
+        to,
+        inclusive: true,
+        linewise: false,
+    };
+    let target = match motion {
+        Motion::Left => exclusive(cursor.saturating_sub(count).max(line_start(chars, cursor))),
+        Motion::Right => exclusive(cursor.saturating_add(count).min(line_end(chars, cursor))),
+        Motion::Up | Motion::Down => {
+            let column = cursor - line_start(chars, cursor);
+            let mut line = line_start(chars, cursor);
+            for _ in 0..count {
+                line = match motion {
+                    Motion::Up if line > 0 => line_start(chars, line - 1),
+                    Motion::Down if line_end(chars, line) < chars.len() => line_end(chars, line) + 1,
+                    _ => break,
+                };
+            }
+            MotionTarget {
+                to: (line + column).min(line_end(chars, line)),
+                inclusive: false,
+                linewise: true,
+            }
+        }
+        Motion::WordForward => exclusive((0..count).fold(cursor, |pos, _| next_word_start(chars, pos))),
+        Motion::WordBackward => exclusive((0..count).fold(cursor, |pos, _| prev_word_start(chars, pos))),
+        Motion::WordEnd => inclusive((0..count).fold(cursor, |pos, _| next_word_end(chars, pos))),
+        Motion::LineStart => exclusive(line_start(chars, cursor)),
+        Motion::FirstNonBlank => exclusive(first_non_blank(chars, cursor)),
+        Motion::LineEnd => {
+            let mut line = line_start(chars, cursor);
+            for _ in 1..count {
+                if line_end(chars, line) < chars.len() {
+                    line = line_end(chars, line) + 1;
+                }
+            }
+            let end = line_end(chars, line);
+            if end == line {
+                exclusive(line)
+            } else {
+                inclusive(end - 1)
+            }
+        }
+        Motion::FindForward(c) | Motion::TillForward(c) => {
+            let end = line_end(chars, cursor);
+            let found = (cursor + 1..end).filter(|&idx| chars[idx] == c).nth(count - 1)?;
+            inclusive(if let Motion::TillForward(_) = motion { found - 1 } else { found })
+        }
+        Motion::FindBackward(c) | Motion::TillBackward(c) => {
+            let start = line_start(chars, cursor);
+            let found = (start..cursor).rev().filter(|&idx| chars[idx] == c).nth(count - 1)?;
+            exclusive(if let Motion::TillBackward(_) = motion { found + 1 } else { found })
+        }
+    };
+    Some(target)
+}
+
+/// class of a character for word motions: whitespace, keyword characters and other symbols
+fn char_class(c: char) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+fn is_blank(c: Option<&char>) -> bool {
+    c.map(|c| c.is_whitespace()).unwrap_or(true)
+}
+
+fn next_word_start(chars: &[char], pos: usize) -> usize {
+    let mut pos = pos;
+    if let Some(&c) = chars.get(pos) {
+        let class = char_class(c);
+        while pos < chars.len() && class != 0 && char_class(chars[pos]) == class {
+            pos += 1;
+        }
+    }
+    while pos < chars.len() && chars[pos].is_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+fn prev_word_start(chars: &[char], pos: usize) -> usize {
+    let mut pos = pos;
+    while pos > 0 && chars[pos - 1].is_whitespace() {
+        pos -= 1;
+    }
+    if pos > 0 {
+        let class = char_class(chars[pos - 1]);
+        while pos > 0 && char_class(chars[pos - 1]) == class {
+            pos -= 1;
+        }
+    }
+    pos
+}
+
+fn next_word_end(chars: &[char], pos: usize) -> usize {
+    let mut pos = pos + 1;
+    while pos < chars.len() && chars[pos].is_whitespace() {
+        pos += 1;
+    }
+    if pos >= chars.len() {
+        return chars.len().saturating_sub(1);
+    }
+    let class = char_class(chars[pos]);
+    while pos + 1 < chars.len() && char_class(chars[pos + 1]) == class {
+        pos += 1;
+    }
+    pos
+}
+
+fn line_start(chars: &[char], pos: usize) -> usize {
+    chars[..pos.min(chars.len())].iter().rposition(|&c| c == '\n').map(|idx| idx + 1).unwrap_or(0)
+}
+
+/// the position of the newline ending the line, or the end of the text
+fn line_end(chars: &[char], pos: usize) -> usize {
+    chars[pos.min(chars.len())..]
+        .iter()
+        .position(|&c| c == '\n')
+        .map(|idx| pos + idx)
+        .unwrap_or_else(|| chars.len())
+}
+
+fn first_non_blank(chars: &[char], pos: usize) -> usize {
+    let start = line_start(chars, pos);
+    let end = line_end(chars, pos);
+    (start..end).find(|&idx| !chars[idx].is_whitespace()).unwrap_or(end)
+}
+
+/// in normal mode, the cursor is always on a character, never behind the end of a line
+fn clamp_cursor(editor: &mut EditorState) {
+    if editor.cursor_col > 0 && editor.cursor_col >= editor.current_line().len() {
+        editor.cursor_col = editor.prev_char_index();
+    }
+}
+
+fn char_idx(text: &str, byte_offset: usize) -> usize {
+    text[..byte_offset].chars().count()
+}
+
+fn byte_offset(text: &str, char_idx: usize) -> usize {
+    text.char_indices().nth(char_idx).map(|(offset, _)| offset).unwrap_or_else(|| text.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn editor_with(content: &str, cursor: usize) -> EditorState {
+        let mut editor = EditorState::new();
+        editor.set_content(content.split('\n').map(|line| line.to_string()).collect());
+        editor.set_cursor_offset(cursor);
+        editor
+    }
+
+    fn type_keys(vi: &mut ViState, editor: &mut EditorState, keys: &str) {
+        for c in keys.chars() {
+            let code = if c == '\x1b' { KeyCode::Esc } else { KeyCode::Char(c) };
+            vi.handle_key(editor, &Keybindings::default(), code, KeyModifiers::NONE);
+        }
+    }
+
+    fn normal_mode() -> ViState {
+        ViState {
+            mode: ViMode::Normal,
+            ..ViState::new()
+        }
+    }
+
+    fn content(editor: &EditorState) -> String {
+        editor.content_lines().join("\n")
+    }
+
+    #[test]
+    fn test_parse() {
+        let parse = |keys: &str| parse_normal_command(&keys.chars().collect::<Vec<_>>());
+        assert_eq!(parse("d"), Parsed::Incomplete);
+        assert_eq!(parse("2d3"), Parsed::Incomplete);
+        assert_eq!(parse("df"), Parsed::Incomplete);
+        assert_eq!(parse("dq"), Parsed::Invalid);
+        assert_eq!(
+            parse("2d3w"),
+            Parsed::Command(NormalCommand {
+                count: Some(6),
+                action: Action::Operate(Operator::Delete, Some(Motion::WordForward))
+            })
+        );
+        assert_eq!(
+            parse("cc"),
+            Parsed::Command(NormalCommand {
+                count: None,
+                action: Action::Operate(Operator::Change, None)
+            })
+        );
+        assert_eq!(
+            parse("0"),
+            Parsed::Command(NormalCommand {
+                count: None,
+                action: Action::Move(Motion::LineStart)
+            })
+        );
+        assert_eq!(
+            parse("10x"),
+            Parsed::Command(NormalCommand {
+                count: Some(10),
+                action: Action::Simple('x')
+            })
+        );
+    }
+
+    #[test]
+    fn test_huge_counts() {
+        let parse = |keys: &str| parse_normal_command(&keys.chars().collect::<Vec<_>>());
+        assert_eq!(
+            parse("9999999999d9999999999w"),
+            Parsed::Command(NormalCommand {
+                count: Some(MAX_COUNT),
+                action: Action::Operate(Operator::Delete, Some(Motion::WordForward))
+            })
+        );
+
+        let mut vi = normal_mode();
+        let mut editor = editor_with("a b c
+d", 0);
+        type_keys(&mut vi, &mut editor, "18446744073709551615l");
+        assert_eq!(editor.cursor_offset(), 4);
+        type_keys(&mut vi, &mut editor, "99999999999999999999dd");
+        assert_eq!(content(&editor), "");
+        type_keys(&mut vi, &mut editor, "u09999999999d9999999999w");
+        assert_eq!(content(&editor), "\nd");
+        type_keys(&mut vi, &mut editor, "u0yl99999999999999p");
+        assert_eq!(content(&editor).len(), "a b c\nd".len() + MAX_COUNT);
+    }
+
+    #[test]
+    fn test_motions() {
+        let chars = "cat foo.txt | grep -v bar".chars().collect::<Vec<_>>();
+        assert_eq!(next_word_start(&chars, 0), 4);
+        assert_eq!(next_word_start(&chars, 4), 7);
+        assert_eq!(next_word_start(&chars, 7), 8);
+        assert_eq!(next_word_end(&chars, 4), 6);
+        assert_eq!(next_word_end(&chars, 6), 7);
+        assert_eq!(prev_word_start(&chars, 8), 7);
+        assert_eq!(prev_word_start(&chars, 4), 0);
+        let target = apply_motion(&chars, 0, Motion::FindForward('r'), 1).unwrap();
+        assert_eq!(target.to, 15);
+        assert!(apply_motion(&chars, 0, Motion::FindForward('z'), 1).is_none());
+    }
+
+    #[test]
+    fn test_operators() {
+        let mut vi = normal_mode();
+        let mut editor = editor_with("cat foo.txt | grep bar", 0);
+        type_keys(&mut vi, &mut editor, "dw");
+        assert_eq!(content(&editor), "foo.txt | grep bar");
+        type_keys(&mut vi, &mut editor, "2dw");
+        assert_eq!(content(&editor), "txt | grep bar");
+        type_keys(&mut vi, &mut editor, "$bd$");
+        assert_eq!(content(&editor), "txt | grep ");
+        type_keys(&mut vi, &mut editor, "0ct|less \x1b");
+        assert_eq!(content(&editor), "less | grep ");
+        assert_eq!(vi.mode, ViMode::Normal);
+        type_keys(&mut vi, &mut editor, "0yeP");
+        assert_eq!(content(&editor), "lessless | grep ");
+        type_keys(&mut vi, &mut editor, "u");
+        assert_eq!(content(&editor), "less | grep ");
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut vi = normal_mode();
+        let mut editor = editor_with("a\nb\nc", 0);
+        type_keys(&mut vi, &mut editor, "ddp");
+        assert_eq!(content(&editor), "b\na\nc");
+        type_keys(&mut vi, &mut editor, "jdd");
+        assert_eq!(content(&editor), "b\na");
+        type_keys(&mut vi, &mut editor, "oc\x1b");
+        assert_eq!(content(&editor), "b\na\nc");
+        type_keys(&mut vi, &mut editor, "kcca\x1b");
+        assert_eq!(content(&editor), "b\na\nc");
+    }
+
+    #[test]
+    fn test_repeat() {
+        let mut vi = normal_mode();
+        let mut editor = editor_with("one two three four five", 0);
+        type_keys(&mut vi, &mut editor, "dw.");
+        assert_eq!(content(&editor), "three four five");
+        type_keys(&mut vi, &mut editor, "cwx\x1bw.");
+        assert_eq!(content(&editor), "x x five");
+        type_keys(&mut vi, &mut editor, "0x2.");
+        assert_eq!(content(&editor), " five");
+
+        let mut editor = editor_with("a b c d e f g", 0);
+        type_keys(&mut vi, &mut editor, "d2w.");
+        assert_eq!(content(&editor), "e f g");
+    }
+
+    #[test]
+    fn test_visual() {
+        let mut vi = normal_mode();
+        let mut editor = editor_with("cat foo | wc", 0);
+        type_keys(&mut vi, &mut editor, "wv");
+        assert_eq!(vi.visual_selection(&editor), Some(4..5));
+        type_keys(&mut vi, &mut editor, "e");
+        assert_eq!(vi.visual_selection(&editor), Some(4..7));
+        type_keys(&mut vi, &mut editor, "d");
+        assert_eq!(content(&editor), "cat  | wc");
+        assert_eq!(vi.mode, ViMode::Normal);
+        assert_eq!(vi.visual_selection(&editor), None);
+    }
+}